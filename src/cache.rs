@@ -0,0 +1,322 @@
+//! Retention policy for assembled packages in a [`Storage`](../persistence/trait.Storage.html) and
+//! garbage collection of the storage.
+//!
+//! Packages are kept after installation, so they can be used for rollbacks, see
+//! [`rollback_candidate`](fn.rollback_candidate.html). How many of them are kept is configured with
+//! the `keep_versions` and `cache_size` keys of the `client` section.
+
+use configuration::ClientConfiguration;
+use handler::Transfers;
+use message::PackageId;
+use persistence::{Storage, CachedPackage};
+
+/// Enforce the configured retention policy, deleting all packages exceeding `keep_versions` per
+/// package name and then the least recently used packages, until the cache fits into
+/// `cache_size`. A `keep_versions` of `0` is only applied after installations, see
+/// [`installed`](fn.installed.html). The `pinned` packages are never deleted, but still count
+/// against both limits.
+///
+/// # Arguments
+/// * `storage`: The storage holding the packages.
+/// * `conf`: The `client` section of the configuration.
+/// * `pinned`: Packages, that are still needed, e.g. the downloads of a pending campaign.
+pub fn enforce_retention(storage: &Storage, conf: &ClientConfiguration, pinned: &[PackageId]) {
+    let mut packages = try_or!(storage.packages(), return);
+
+    // most recently used first
+    packages.sort_by(|a, b| b.used.cmp(&a.used));
+
+    let keep = conf.keep_versions.and_then(|k| if k > 0 { Some(k as usize) } else { None });
+    if let Some(keep) = keep {
+        let mut retained: Vec<CachedPackage> = Vec::new();
+        for package in packages {
+            let versions = retained.iter()
+                .filter(|p| p.package.name == package.package.name)
+                .count();
+            if versions < keep || pinned.contains(&package.package) {
+                retained.push(package);
+            } else {
+                remove(storage, &package, "exceeds the number of versions to keep");
+            }
+        }
+        packages = retained;
+    }
+
+    if let Some(max) = conf.cache_size {
+        let max = if max < 0 { 0 } else { max as u64 };
+        let mut total = packages.iter().fold(0, |sum, p| sum + p.size);
+        for package in packages.iter().rev().filter(|p| !pinned.contains(&p.package)) {
+            if total <= max {
+                break;
+            }
+            total -= package.size;
+            remove(storage, package, "exceeds the cache size");
+        }
+    }
+}
+
+/// Apply the retention policy after `package` was installed. Removes the package, if
+/// `keep_versions` is set to `0`, and marks it as used otherwise. Failed installations leave the
+/// cache untouched, so a broken version doesn't displace older, working ones.
+///
+/// # Arguments
+/// * `storage`: The storage holding the packages.
+/// * `conf`: The `client` section of the configuration.
+/// * `package`: The package that was installed.
+/// * `success`: Whether the installation was successful.
+/// * `pinned`: Packages, that must not be deleted.
+pub fn installed(storage: &Storage, conf: &ClientConfiguration, package: &PackageId,
+                 success: bool, pinned: &[PackageId]) {
+    if !success {
+        return;
+    }
+    if conf.keep_versions == Some(0) {
+        info!("Removing installed package {}", package);
+        try_or!(storage.remove_package(package), {});
+    } else {
+        try_or!(storage.touch_package(package), {});
+    }
+    enforce_retention(storage, conf, pinned);
+}
+
+/// Apply the retention policy after a failed installation was rolled back to `package`. The
+/// restored version is marked as used and always kept, even with a `keep_versions` of `0`, so
+/// the next failed installation can be rolled back as well.
+///
+/// # Arguments
+/// * `storage`: The storage holding the packages.
+/// * `conf`: The `client` section of the configuration.
+/// * `package`: The package that was reinstalled.
+/// * `pinned`: Further packages, that must not be deleted.
+pub fn restored(storage: &Storage, conf: &ClientConfiguration, package: &PackageId,
+                pinned: &[PackageId]) {
+    try_or!(storage.touch_package(package), {});
+    let mut pinned = pinned.to_vec();
+    pinned.push(package.clone());
+    enforce_retention(storage, conf, &pinned);
+}
+
+/// Find the cached package, a failed installation of `package` can be rolled back to. That is the
+/// currently `installed` version of the same package, if it's still in the cache.
+///
+/// # Arguments
+/// * `storage`: The storage holding the packages.
+/// * `installed`: The packages installed on this device, as reported by the software manager.
+/// * `package`: The package that is about to be installed.
+pub fn rollback_candidate(storage: &Storage, installed: &[PackageId], package: &PackageId)
+    -> Option<PackageId> {
+    installed.iter()
+        .find(|p| p.name == package.name && p.version != package.version)
+        .and_then(|p| if storage.has_package(p) { Some(p.clone()) } else { None })
+}
+
+/// Remove everything from the storage, that isn't needed anymore. These are downloads, that don't
+/// belong to one of the `transfers`, and everything, that is neither a download nor a package,
+/// e.g. leftovers of an interrupted assembly.
+///
+/// Should be called after [`enforce_retention`](fn.enforce_retention.html). Pass the `Transfers`
+/// [`restore`](../handler/struct.Transfers.html#method.restore)d from the storage, to keep the
+/// downloads, that can still be resumed.
+///
+/// # Arguments
+/// * `storage`: The storage holding chunks and packages.
+/// * `transfers`: The transfers, whose chunks should be kept.
+pub fn collect_garbage(storage: &Storage, transfers: &Transfers) {
+    for package in try_or!(storage.downloads(), return) {
+        if !transfers.active.contains_key(&package) {
+            info!("Removing orphaned download {}", package);
+            try_or!(storage.remove_chunks(&package), continue);
+        }
+    }
+
+    try_or!(storage.remove_stray(), return);
+}
+
+/// Remove a cached package and log why it was removed.
+///
+/// # Arguments
+/// * `storage`: The storage holding the package.
+/// * `package`: The package to remove.
+/// * `reason`: Why the package gets removed.
+fn remove(storage: &Storage, package: &CachedPackage, reason: &str) {
+    info!("Removing cached package {}, as it {}", package.package, reason);
+    try_or!(storage.remove_package(&package.package), return);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::ffi::CString;
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::prelude::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use libc;
+
+    use configuration::ClientConfiguration;
+    use handler::Transfers;
+    use message::PackageId;
+    use persistence::{Storage, FileStorage, package_path};
+
+    fn store(prefix: &PathPrefix, name: &str, version: &str, mtime: i64) -> PackageId {
+        let package = PackageId {
+            name: name.to_string(),
+            version: version.to_string()
+        };
+        let path = package_path(&prefix.to_string(), &package).unwrap();
+        OpenOptions::new().write(true).create(true).open(&path).unwrap()
+            .write_all(b"0123456789").unwrap();
+
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let times = libc::utimbuf { actime: mtime, modtime: mtime };
+        assert_eq!(unsafe { libc::utime(c_path.as_ptr(), &times) }, 0);
+        package
+    }
+
+    fn cached(storage: &Storage) -> Vec<PackageId> {
+        let mut packages: Vec<PackageId> = storage.packages().unwrap()
+            .into_iter().map(|p| p.package).collect();
+        packages.sort_by(|a, b| a.version.cmp(&b.version));
+        packages
+    }
+
+    fn gen_conf(keep: Option<i32>, size: Option<i64>) -> ClientConfiguration {
+        let mut conf = ClientConfiguration::gen_test();
+        conf.keep_versions = keep;
+        conf.cache_size = size;
+        conf
+    }
+
+    #[test]
+    fn it_keeps_the_last_versions_of_each_package() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a1 = store(&prefix, "a", "1", 1000);
+        let a2 = store(&prefix, "a", "2", 2000);
+        let a3 = store(&prefix, "a", "3", 3000);
+        let b1 = store(&prefix, "b", "1", 1000);
+
+        enforce_retention(&storage, &gen_conf(Some(2), None), &[]);
+        let packages = cached(&storage);
+        assert!(!packages.contains(&a1));
+        assert!(packages.contains(&a2));
+        assert!(packages.contains(&a3));
+        assert!(packages.contains(&b1));
+    }
+
+    #[test]
+    fn it_evicts_the_least_recently_used_packages() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a = store(&prefix, "a", "1", 3000);
+        let b = store(&prefix, "b", "2", 1000);
+        let c = store(&prefix, "c", "3", 2000);
+
+        enforce_retention(&storage, &gen_conf(None, Some(20)), &[]);
+        let packages = cached(&storage);
+        assert!(!packages.contains(&b));
+        assert_eq!(packages, vec!(a, c));
+    }
+
+    #[test]
+    fn it_deletes_successfully_installed_packages() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a = store(&prefix, "a", "1", 1000);
+        let b = store(&prefix, "b", "2", 1000);
+        let conf = gen_conf(Some(0), None);
+
+        installed(&storage, &conf, &a, false, &[]);
+        assert_eq!(cached(&storage), vec!(a.clone(), b.clone()));
+        installed(&storage, &conf, &a, true, &[]);
+        assert_eq!(cached(&storage), vec!(b));
+    }
+
+    #[test]
+    fn it_keeps_pinned_and_restored_packages() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a1 = store(&prefix, "a", "1", 1000);
+        let b2 = store(&prefix, "b", "2", 2000);
+        let c3 = store(&prefix, "c", "3", 3000);
+
+        enforce_retention(&storage, &gen_conf(Some(1), Some(10)), &[a1.clone(), b2.clone()]);
+        assert_eq!(cached(&storage), vec!(a1.clone(), b2.clone()));
+
+        restored(&storage, &gen_conf(Some(0), None), &a1, &[]);
+        assert_eq!(cached(&storage), vec!(a1, b2));
+    }
+
+    #[test]
+    fn it_doesnt_touch_failed_packages() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a1 = store(&prefix, "a", "1", 1000);
+        let a2 = store(&prefix, "a", "2", 2000);
+
+        installed(&storage, &gen_conf(None, None), &a1, false, &[]);
+        enforce_retention(&storage, &gen_conf(Some(1), None), &[]);
+        assert_eq!(cached(&storage), vec!(a2));
+    }
+
+    #[test]
+    fn it_rolls_back_to_the_installed_version() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let a1 = store(&prefix, "a", "1", 1000);
+        let a2 = PackageId { name: "a".to_string(), version: "2".to_string() };
+        let b1 = PackageId { name: "b".to_string(), version: "1".to_string() };
+        let b2 = PackageId { name: "b".to_string(), version: "2".to_string() };
+        let installed = vec!(a1.clone(), b1);
+
+        assert_eq!(rollback_candidate(&storage, &installed, &a2), Some(a1));
+        assert_eq!(rollback_candidate(&storage, &installed, &b2), None);
+    }
+
+    #[test]
+    fn it_keeps_only_resumable_downloads() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = Arc::new(FileStorage::new(&prefix.to_string()));
+        let package = generate_random_package(10);
+        storage.write_chunk(&package, 0, b"test").unwrap();
+        storage.write_transfer(&package, br#"{"checksum":"","base":null,"encoding":"identity",
+                                           "chunkscount":2}"#).unwrap();
+
+        let orphan = PathBuf::from(format!("{}/downloads/orphan-1.0", prefix));
+        fs::create_dir_all(&orphan).unwrap();
+        OpenOptions::new().write(true).create(true)
+            .open(format!("{}/0", orphan.display())).unwrap();
+
+        let transfers = Transfers::restore(storage.clone());
+        collect_garbage(&*storage, &transfers);
+
+        assert!(fs::metadata(&orphan).is_err());
+        let kept = format!("{}/downloads/{}-{}/0", prefix, package.name, package.version);
+        assert!(fs::metadata(PathBuf::from(kept)).is_ok());
+    }
+
+    #[test]
+    fn it_removes_stray_files_from_the_packages() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let package = store(&prefix, "a", "1", 1000);
+        let stray = PathBuf::from(format!("{}/packages/.a-2.spkg.tmp", prefix));
+        OpenOptions::new().write(true).create(true).open(&stray).unwrap();
+
+        collect_garbage(&storage, &Transfers::new_test());
+        assert!(fs::metadata(&stray).is_err());
+        assert_eq!(cached(&storage), vec!(package));
+    }
+}
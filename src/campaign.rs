@@ -0,0 +1,159 @@
+//! Campaigns of several packages, that depend on each other.
+//!
+//! A notification becomes a campaign, once one of its packages `requires` others. All packages of
+//! a campaign are downloaded first and then installed in dependency order. Should a package fail
+//! to install, the rest of the campaign is aborted.
+
+use std::collections::HashSet;
+
+use message::{PackageId, UserPackage};
+
+/// Type for a campaign, tracking which of its packages were downloaded already.
+#[derive(Debug)]
+pub struct Campaign {
+    /// The packages of the campaign, in the order they have to be installed.
+    order: Vec<PackageId>,
+    /// The packages, that finished downloading.
+    downloaded: HashSet<PackageId>
+}
+
+impl Campaign {
+    /// Create a new `Campaign` from the packages of a notification. Orders the packages, so every
+    /// package comes after all packages it requires, and keeps the order of the notification
+    /// otherwise. Requirements outside of the campaign are assumed to be installed already.
+    /// Returns a `String` with a error message, if the requirements form a cycle.
+    ///
+    /// # Arguments
+    /// * `packages`: The packages of the notification.
+    pub fn new(packages: &[UserPackage]) -> Result<Campaign, String> {
+        let ids: Vec<&PackageId> = packages.iter().map(|p| &p.package).collect();
+        let mut order: Vec<PackageId> = Vec::new();
+
+        while order.len() < packages.len() {
+            let next = packages.iter().find(|p| {
+                !order.contains(&p.package) && p.requires.as_ref().map(|r| {
+                    r.iter().all(|dep| !ids.contains(&dep) || order.contains(dep))
+                }).unwrap_or(true)
+            });
+
+            match next {
+                Some(p) => order.push(p.package.clone()),
+                None => {
+                    let pending: Vec<String> = packages.iter()
+                        .filter(|p| !order.contains(&p.package))
+                        .map(|p| format!("{}", p.package))
+                        .collect();
+                    return Err(format!("Dependency cycle between {}", pending.join(", ")));
+                }
+            }
+        }
+
+        Ok(Campaign {
+            order: order,
+            downloaded: HashSet::new()
+        })
+    }
+
+    /// Returns whether any of `packages` requires another package, i.e. the notification is a
+    /// campaign.
+    ///
+    /// # Arguments
+    /// * `packages`: The packages of the notification.
+    pub fn is_campaign(packages: &[UserPackage]) -> bool {
+        packages.iter().any(|p| p.requires.as_ref().map(|r| !r.is_empty()).unwrap_or(false))
+    }
+
+    /// Returns whether `package` belongs to this campaign.
+    ///
+    /// # Arguments
+    /// * `package`: The package to look for.
+    pub fn contains(&self, package: &PackageId) -> bool {
+        self.order.contains(package)
+    }
+
+    /// Mark `package` as downloaded.
+    ///
+    /// # Arguments
+    /// * `package`: The downloaded package.
+    pub fn downloaded(&mut self, package: &PackageId) {
+        let _ = self.downloaded.insert(package.clone());
+    }
+
+    /// Returns whether `package` was downloaded.
+    ///
+    /// # Arguments
+    /// * `package`: The package to look for.
+    pub fn is_downloaded(&self, package: &PackageId) -> bool {
+        self.downloaded.contains(package)
+    }
+
+    /// Returns whether all packages of this campaign were downloaded.
+    pub fn is_complete(&self) -> bool {
+        self.order.iter().all(|p| self.downloaded.contains(p))
+    }
+
+    /// Returns the packages of this campaign, in the order they have to be installed.
+    pub fn order(&self) -> &[PackageId] {
+        &self.order
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::generate_random_package;
+
+    use message::{PackageId, UserPackage};
+
+    fn gen_update(i: usize, requires: Vec<PackageId>) -> UserPackage {
+        UserPackage {
+            package: generate_random_package(i),
+            size: 500,
+            priority: None,
+            mandatory: None,
+            deadline: None,
+            description: None,
+            requires: Some(requires)
+        }
+    }
+
+    #[test]
+    fn it_orders_packages_by_their_requirements() {
+        test_init!();
+        let base = gen_update(10, Vec::new());
+        let app = gen_update(11, vec!(base.package.clone()));
+        let plugin = gen_update(12, vec!(app.package.clone(), generate_random_package(13)));
+        let other = gen_update(14, Vec::new());
+
+        let packages = [plugin.clone(), other.clone(), app.clone(), base.clone()];
+        assert!(Campaign::is_campaign(&packages));
+        let campaign = Campaign::new(&packages).unwrap();
+        assert_eq!(campaign.order(), &[other.package, base.package, app.package,
+                                       plugin.package][..]);
+    }
+
+    #[test]
+    fn it_rejects_dependency_cycles() {
+        test_init!();
+        let mut a = gen_update(10, Vec::new());
+        let b = gen_update(11, vec!(a.package.clone()));
+        a.requires = Some(vec!(b.package.clone()));
+
+        assert!(Campaign::new(&[a, b]).is_err());
+    }
+
+    #[test]
+    fn it_completes_once_all_packages_are_downloaded() {
+        test_init!();
+        let a = gen_update(10, Vec::new());
+        let b = gen_update(11, vec!(a.package.clone()));
+        let mut campaign = Campaign::new(&[a.clone(), b.clone()]).unwrap();
+
+        campaign.downloaded(&b.package);
+        assert!(campaign.is_downloaded(&b.package));
+        assert!(!campaign.is_downloaded(&a.package));
+        assert!(!campaign.is_complete());
+        campaign.downloaded(&a.package);
+        assert!(campaign.is_complete());
+    }
+}
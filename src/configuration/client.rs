@@ -16,7 +16,71 @@ pub struct ClientConfiguration {
     /// How long to wait for further server messages before the `Transfer` will be dropped.
     pub timeout: Option<i64>,
     /// Index of the RVI service URL, that holds the VIN for this device.
-    pub vin_match: i32
+    pub vin_match: i32,
+    /// Maximum amount of bytes, the storage directory may occupy.
+    pub storage_quota: Option<i64>,
+    /// How many versions of each package to keep after installation. `0` deletes packages after
+    /// a successful installation, but keeps versions restored by a rollback.
+    pub keep_versions: Option<i32>,
+    /// Maximum amount of bytes, the cached packages may occupy.
+    pub cache_size: Option<i64>,
+    /// Acknowledge received chunks after this many chunks. Defaults to every chunk.
+    pub ack_batch: Option<i32>,
+    /// Acknowledge pending chunks at least every this many seconds.
+    pub ack_interval: Option<i64>,
+    /// How many chunks the server may send ahead of the first missing chunk, before waiting for
+    /// the next acknowledgement.
+    pub ack_window: Option<i32>,
+    /// How received chunks are listed in acknowledgements. Either `list` or `ranges`.
+    pub ack_format: Option<String>,
+    /// Maximum amount of simultaneous `Transfer`s. Further transfers are queued.
+    pub max_transfers: Option<i32>,
+    /// Maximum amount of queued transfers. Further transfers are rejected with a retry hint.
+    pub max_queued: Option<i32>,
+    /// Seconds, the server is asked to wait before retrying a transfer, that was rejected as all
+    /// slots and the queue are occupied.
+    pub transfer_retry: Option<i64>,
+    /// Shared secret, that signs configuration fragments sent by the server. Remote configuration
+    /// is rejected without it.
+    pub config_key: Option<String>,
+    /// Amount of the most recent log records, that are kept for uploading them to the server.
+    pub log_records: Option<i32>,
+    /// The RVI service on the server, heartbeats are sent to. No heartbeats are sent without it.
+    pub heartbeat_service: Option<String>,
+    /// Seconds between two heartbeats. `0` disables heartbeats.
+    pub heartbeat_interval: Option<i64>,
+    /// Hex encoded Ed25519 public key, that local bundles have to be signed with. Without it,
+    /// only unsigned bundles are accepted.
+    pub bundle_key: Option<String>
+}
+
+#[cfg(test)]
+impl ClientConfiguration {
+    /// Generate a test configuration.
+    pub fn gen_test() -> ClientConfiguration {
+        ClientConfiguration {
+            storage_dir: "/tmp".to_string(),
+            rvi_url: None,
+            edge_url: None,
+            timeout: None,
+            vin_match: 2,
+            storage_quota: None,
+            keep_versions: None,
+            cache_size: None,
+            ack_batch: None,
+            ack_interval: None,
+            ack_window: None,
+            ack_format: None,
+            max_transfers: None,
+            max_queued: None,
+            transfer_retry: None,
+            config_key: None,
+            log_records: None,
+            heartbeat_service: None,
+            heartbeat_interval: None,
+            bundle_key: None
+        }
+    }
 }
 
 impl ConfTreeParser<ClientConfiguration> for ClientConfiguration {
@@ -29,13 +93,51 @@ impl ConfTreeParser<ClientConfiguration> for ClientConfiguration {
         let edge_url = try!(get_optional_key(client_tree, "edge_url", "client"));
         let timeout = try!(get_optional_key(client_tree, "timeout", "client"));
         let vin_match = try!(get_optional_key(client_tree, "vin_match", "client"));
+        let storage_quota = try!(get_optional_key(client_tree, "storage_quota", "client"));
+        let keep_versions = try!(get_optional_key(client_tree, "keep_versions", "client"));
+        let cache_size = try!(get_optional_key(client_tree, "cache_size", "client"));
+        let ack_batch = try!(get_optional_key(client_tree, "ack_batch", "client"));
+        let ack_interval = try!(get_optional_key(client_tree, "ack_interval", "client"));
+        let ack_window = try!(get_optional_key(client_tree, "ack_window", "client"));
+        let ack_format: Option<String> =
+            try!(get_optional_key(client_tree, "ack_format", "client"));
+        let max_transfers = try!(get_optional_key(client_tree, "max_transfers", "client"));
+        let max_queued = try!(get_optional_key(client_tree, "max_queued", "client"));
+        let transfer_retry = try!(get_optional_key(client_tree, "transfer_retry", "client"));
+        let config_key = try!(get_optional_key(client_tree, "config_key", "client"));
+        let log_records = try!(get_optional_key(client_tree, "log_records", "client"));
+        let heartbeat_service =
+            try!(get_optional_key(client_tree, "heartbeat_service", "client"));
+        let heartbeat_interval =
+            try!(get_optional_key(client_tree, "heartbeat_interval", "client"));
+        let bundle_key = try!(get_optional_key(client_tree, "bundle_key", "client"));
+
+        match ack_format.as_ref().map(|f| &f[..]) {
+            None | Some("list") | Some("ranges") => {},
+            Some(f) => return Err(format!("Unknown ack_format \"{}\" in \"client\"", f))
+        }
 
         Ok(ClientConfiguration {
             storage_dir: storage_dir,
             rvi_url: rvi_url,
             edge_url: edge_url,
             timeout: timeout,
-            vin_match: vin_match.unwrap_or(2)
+            vin_match: vin_match.unwrap_or(2),
+            storage_quota: storage_quota,
+            keep_versions: keep_versions,
+            cache_size: cache_size,
+            ack_batch: ack_batch,
+            ack_interval: ack_interval,
+            ack_window: ack_window,
+            ack_format: ack_format,
+            max_transfers: max_transfers,
+            max_queued: max_queued,
+            transfer_retry: transfer_retry,
+            config_key: config_key,
+            log_records: log_records,
+            heartbeat_service: heartbeat_service,
+            heartbeat_interval: heartbeat_interval,
+            bundle_key: bundle_key
         })
     }
 }
@@ -45,6 +147,20 @@ impl ConfTreeParser<ClientConfiguration> for ClientConfiguration {
 #[cfg(test)] static EDGE: &'static str = "localhost:9080";
 #[cfg(test)] static TIMEOUT: i64 = 10;
 #[cfg(test)] static VIN: i32 = 3;
+#[cfg(test)] static QUOTA: i64 = 1048576;
+#[cfg(test)] static KEEP: i32 = 2;
+#[cfg(test)] static CACHE: i64 = 524288;
+#[cfg(test)] static ACK_BATCH: i32 = 16;
+#[cfg(test)] static ACK_INTERVAL: i64 = 5;
+#[cfg(test)] static ACK_WINDOW: i32 = 64;
+#[cfg(test)] static ACK_FORMAT: &'static str = "ranges";
+#[cfg(test)] static MAX_TRANSFERS: i32 = 2;
+#[cfg(test)] static MAX_QUEUED: i32 = 8;
+#[cfg(test)] static TRANSFER_RETRY: i64 = 120;
+#[cfg(test)] static CONFIG_KEY: &'static str = "secret";
+#[cfg(test)] static LOG_RECORDS: i32 = 500;
+#[cfg(test)] static HEARTBEAT_SERVICE: &'static str = "genivi.org/backend/sota/heartbeat";
+#[cfg(test)] static HEARTBEAT_INTERVAL: i64 = 600;
 
 #[cfg(test)]
 pub fn gen_valid_conf() -> String {
@@ -55,7 +171,23 @@ pub fn gen_valid_conf() -> String {
     edge_url = "{}"
     timeout = {}
     vin_match = {}
-    "#, STORAGE, RVI, EDGE, TIMEOUT, VIN)
+    storage_quota = {}
+    keep_versions = {}
+    cache_size = {}
+    ack_batch = {}
+    ack_interval = {}
+    ack_window = {}
+    ack_format = "{}"
+    max_transfers = {}
+    max_queued = {}
+    transfer_retry = {}
+    config_key = "{}"
+    log_records = {}
+    heartbeat_service = "{}"
+    heartbeat_interval = {}
+    "#, STORAGE, RVI, EDGE, TIMEOUT, VIN, QUOTA, KEEP, CACHE,
+    ACK_BATCH, ACK_INTERVAL, ACK_WINDOW, ACK_FORMAT, MAX_TRANSFERS, MAX_QUEUED, TRANSFER_RETRY,
+    CONFIG_KEY, LOG_RECORDS, HEARTBEAT_SERVICE, HEARTBEAT_INTERVAL)
 }
 
 #[cfg(test)]
@@ -65,6 +197,20 @@ pub fn assert_conf(configuration: &ClientConfiguration) -> bool {
     assert_eq!(&configuration.edge_url.clone().unwrap(), EDGE);
     assert_eq!(configuration.timeout.unwrap(), TIMEOUT);
     assert_eq!(configuration.vin_match, VIN);
+    assert_eq!(configuration.storage_quota.unwrap(), QUOTA);
+    assert_eq!(configuration.keep_versions.unwrap(), KEEP);
+    assert_eq!(configuration.cache_size.unwrap(), CACHE);
+    assert_eq!(configuration.ack_batch.unwrap(), ACK_BATCH);
+    assert_eq!(configuration.ack_interval.unwrap(), ACK_INTERVAL);
+    assert_eq!(configuration.ack_window.unwrap(), ACK_WINDOW);
+    assert_eq!(&configuration.ack_format.clone().unwrap(), ACK_FORMAT);
+    assert_eq!(configuration.max_transfers.unwrap(), MAX_TRANSFERS);
+    assert_eq!(configuration.max_queued.unwrap(), MAX_QUEUED);
+    assert_eq!(configuration.transfer_retry.unwrap(), TRANSFER_RETRY);
+    assert_eq!(&configuration.config_key.clone().unwrap(), CONFIG_KEY);
+    assert_eq!(configuration.log_records.unwrap(), LOG_RECORDS);
+    assert_eq!(&configuration.heartbeat_service.clone().unwrap(), HEARTBEAT_SERVICE);
+    assert_eq!(configuration.heartbeat_interval.unwrap(), HEARTBEAT_INTERVAL);
     true
 }
 
@@ -174,4 +320,67 @@ pub mod test {
         assert_eq!(configuration.timeout.unwrap(), TIMEOUT);
         assert_eq!(configuration.vin_match, 2);
     }
+
+    #[test]
+    fn it_doesnt_require_the_storage_keys() {
+        test_init!();
+        let data = format!(r#"
+        [client]
+        storage_dir = "{}"
+        "#, STORAGE);
+
+        let tree = read_tree(&data).unwrap();
+        let configuration = ClientConfiguration::parse(&tree).unwrap();
+        assert_eq!(&configuration.storage_dir, STORAGE);
+        assert_eq!(configuration.storage_quota, None);
+        assert_eq!(configuration.keep_versions, None);
+        assert_eq!(configuration.cache_size, None);
+    }
+
+    #[test]
+    fn it_doesnt_require_the_ack_keys() {
+        test_init!();
+        let data = format!(r#"
+        [client]
+        storage_dir = "{}"
+        "#, STORAGE);
+
+        let tree = read_tree(&data).unwrap();
+        let configuration = ClientConfiguration::parse(&tree).unwrap();
+        assert_eq!(configuration.ack_batch, None);
+        assert_eq!(configuration.ack_interval, None);
+        assert_eq!(configuration.ack_window, None);
+        assert_eq!(configuration.ack_format, None);
+    }
+
+    #[test]
+    fn it_rejects_unknown_ack_formats() {
+        test_init!();
+        let data = format!(r#"
+        [client]
+        storage_dir = "{}"
+        ack_format = "bitmap"
+        "#, STORAGE);
+
+        let tree = read_tree(&data).unwrap();
+        match ClientConfiguration::parse(&tree) {
+            Ok(..) => panic!("Accepted invalid configuration!"),
+            Err(e) => assert_eq!(e, "Unknown ack_format \"bitmap\" in \"client\"".to_string())
+        };
+    }
+
+    #[test]
+    fn it_doesnt_require_the_transfer_limits() {
+        test_init!();
+        let data = format!(r#"
+        [client]
+        storage_dir = "{}"
+        "#, STORAGE);
+
+        let tree = read_tree(&data).unwrap();
+        let configuration = ClientConfiguration::parse(&tree).unwrap();
+        assert_eq!(configuration.max_transfers, None);
+        assert_eq!(configuration.max_queued, None);
+        assert_eq!(configuration.transfer_retry, None);
+    }
 }
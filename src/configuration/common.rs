@@ -87,6 +87,25 @@ impl ParseTomlValue for i64 {
     }
 }
 
+impl ParseTomlValue for bool {
+    fn parse(val: &toml::Value, key: &str, group: &str)
+        -> Result<bool> {
+        val.as_bool()
+           .ok_or(format!("Key \"{}\" in \"{}\" is not a boolean", key, group))
+    }
+}
+
+impl ParseTomlValue for Vec<String> {
+    fn parse(val: &toml::Value, key: &str, group: &str)
+        -> Result<Vec<String>> {
+        let error = format!("Key \"{}\" in \"{}\" is not a list of strings", key, group);
+        let values = try!(val.as_slice().ok_or(error.clone()));
+        values.iter()
+            .map(|v| v.as_str().map(|s| s.to_string()).ok_or(error.clone()))
+            .collect()
+    }
+}
+
 /// Helper function to format a `toml::Parser` error message to the format used in this
 /// implementation. This is only safe to call if the `parser` is associated with a *real* file on
 /// disk.
@@ -2,13 +2,29 @@
 
 use toml;
 use std::io::prelude::*;
-use std::path::PathBuf;
-use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::env;
 
 use super::common::{ConfTreeParser, format_parser_error, stringify, Result};
 use super::client::ClientConfiguration;
 use super::dbus::DBusConfiguration;
+use super::policy::PolicyConfiguration;
+use super::maintenance::MaintenanceConfiguration;
+use super::vehicle::VehicleConfiguration;
+
+/// Keys per section, that are only read at startup. Remote configuration must not change them, as
+/// the change wouldn't take effect before a restart.
+const STARTUP_KEYS: &'static [(&'static str, &'static str)] = &[
+    ("client", "storage_dir"),
+    ("client", "rvi_url"),
+    ("client", "edge_url"),
+    ("client", "vin_match"),
+    ("client", "log_records"),
+    ("dbus", "name"),
+    ("dbus", "interface")
+];
 
 /// Type to encode the full configuration.
 #[derive(Clone)]
@@ -16,23 +32,27 @@ pub struct Configuration {
     /// The `client` section of the configuration
     pub client: ClientConfiguration,
     /// The `dbus` section of the configuration
-    pub dbus: DBusConfiguration
+    pub dbus: DBusConfiguration,
+    /// The optional `policy` section of the configuration
+    pub policy: PolicyConfiguration,
+    /// The optional `maintenance` section of the configuration
+    pub maintenance: MaintenanceConfiguration,
+    /// The optional `vehicle` section of the configuration
+    pub vehicle: VehicleConfiguration
 }
 
 impl Configuration {
     /// Try to read the configuration from the provided path and parse it into a `Configuration`
-    /// object. Returns the parsed `Configuration` on success or the first error message
-    /// encountered while reading or parsing the configuration file.
+    /// object. Keys of the remote configuration next to it take precedence over the ones in the
+    /// file. Returns the parsed `Configuration` on success or the first error message
+    /// encountered while reading or parsing the configuration files.
     ///
     /// # Arguments
     /// * `path`: Path to the location of the configuration file.
     pub fn read(path: &str) -> Result<Configuration> {
-        let path = PathBuf::from(path);
-        let mut f = try!(OpenOptions::new().open(path).map_err(stringify));
-        let mut buf = Vec::new();
-        try!(f.read_to_end(&mut buf).map_err(stringify));
-        let data = try!(String::from_utf8(buf).map_err(stringify));
-        Configuration::parse(&data)
+        let mut tree = try!(parse_toml(&try!(read_file(path))));
+        merge(&mut tree, try!(read_remote(path)));
+        Configuration::from_tree(&tree)
     }
 
     /// Try to parse the given string to a `Configuration`.
@@ -40,15 +60,49 @@ impl Configuration {
     /// # Arguments
     /// * `conf`: The configuration to parse.
     pub fn parse(conf: &str) -> Result<Configuration> {
-        let mut parser = toml::Parser::new(conf);
-        let tree = try!(parser.parse().ok_or(format_parser_error(&parser)));
+        Configuration::from_tree(&try!(parse_toml(conf)))
+    }
+
+    /// Validate a configuration `fragment` received from the server and store it with the remote
+    /// configuration next to the configuration file. The file is only replaced, if the merged
+    /// configuration is valid and the fragment doesn't change any key, that is only read at
+    /// startup. Returns the new `Configuration` or the first error message encountered.
+    ///
+    /// # Arguments
+    /// * `path`: Path to the location of the configuration file.
+    /// * `fragment`: The sections and keys to change.
+    pub fn update_remote(path: &str, fragment: &str) -> Result<Configuration> {
+        let fragment = try!(parse_toml(fragment));
+        try!(check_startup_keys(&fragment));
+        let mut remote = try!(read_remote(path));
+        merge(&mut remote, fragment);
 
-        let client = try!(ClientConfiguration::parse(&tree));
-        let dbus   = try!(DBusConfiguration::parse(&tree));
+        let mut tree = try!(parse_toml(&try!(read_file(path))));
+        merge(&mut tree, remote.clone());
+        let configuration = try!(Configuration::from_tree(&tree));
+
+        let data = toml::Value::Table(remote).to_string();
+        try!(write_atomically(&remote_path(path), &data));
+        Ok(configuration)
+    }
+
+    /// Parse all sections of the configuration from a `toml` tree.
+    ///
+    /// # Arguments
+    /// * `tree`: The `toml` tree to parse.
+    fn from_tree(tree: &toml::Table) -> Result<Configuration> {
+        let client = try!(ClientConfiguration::parse(tree));
+        let dbus   = try!(DBusConfiguration::parse(tree));
+        let policy = try!(PolicyConfiguration::parse(tree));
+        let maintenance = try!(MaintenanceConfiguration::parse(tree));
+        let vehicle = try!(VehicleConfiguration::parse(tree));
 
         Ok(Configuration {
             client: client,
-            dbus: dbus
+            dbus: dbus,
+            policy: policy,
+            maintenance: maintenance,
+            vehicle: vehicle
         })
     }
 
@@ -76,12 +130,135 @@ impl Configuration {
     }
 }
 
+/// Returns the path of the remote configuration, that belongs to the configuration file at
+/// `path`.
+///
+/// # Arguments
+/// * `path`: Path to the location of the configuration file.
+fn remote_path(path: &str) -> String {
+    format!("{}.remote", path)
+}
+
+/// Check that `fragment` doesn't change any of the `STARTUP_KEYS`.
+///
+/// # Arguments
+/// * `fragment`: The `toml` tree of a remote configuration fragment.
+fn check_startup_keys(fragment: &toml::Table) -> Result<()> {
+    for &(section, key) in STARTUP_KEYS {
+        if let Some(&toml::Value::Table(ref keys)) = fragment.get(section) {
+            if keys.contains_key(key) {
+                return Err(format!("Changing \"{}\" in \"{}\" requires a restart", key, section));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the remote configuration, that belongs to the configuration file at `path`. Returns a
+/// empty tree, if there is none.
+///
+/// # Arguments
+/// * `path`: Path to the location of the configuration file.
+fn read_remote(path: &str) -> Result<toml::Table> {
+    let remote = remote_path(path);
+    if !Path::new(&remote).exists() {
+        return Ok(toml::Table::new());
+    }
+    read_file(&remote).and_then(|data| parse_toml(&data))
+        .map_err(|e| format!("Invalid remote configuration {}: {}", remote, e))
+}
+
+/// Read the file at `path` to a `String`.
+///
+/// # Arguments
+/// * `path`: Path to the file to read.
+fn read_file(path: &str) -> Result<String> {
+    let path = PathBuf::from(path);
+    let mut f = try!(OpenOptions::new().read(true).open(path).map_err(stringify));
+    let mut buf = Vec::new();
+    try!(f.read_to_end(&mut buf).map_err(stringify));
+    String::from_utf8(buf).map_err(stringify)
+}
+
+/// Write `data` to a temporary file and move it to `path` afterwards, so `path` never holds a
+/// partially written file. Like committed packages, the file and its directory are synced, so the
+/// new file survives a power loss.
+///
+/// # Arguments
+/// * `path`: Path to the file to replace.
+/// * `data`: The new content of the file.
+fn write_atomically(path: &str, data: &str) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut f = try!(File::create(&tmp).map_err(stringify));
+        try!(f.write_all(data.as_bytes()).map_err(stringify));
+        try!(f.sync_all().map_err(stringify));
+    }
+    try!(fs::rename(&tmp, path).map_err(stringify));
+
+    // Sync the directory, so the rename is persisted
+    let dir = match Path::new(path).parent() {
+        Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+        _ => PathBuf::from(".")
+    };
+    File::open(&dir).and_then(|d| d.sync_all())
+        .map_err(|e| format!("Couldn't sync dir {}: {}", dir.display(), e))
+}
+
+/// Parse `conf` to a `toml` tree.
+///
+/// # Arguments
+/// * `conf`: The configuration to parse.
+fn parse_toml(conf: &str) -> Result<toml::Table> {
+    let mut parser = toml::Parser::new(conf);
+    parser.parse().ok_or(format_parser_error(&parser))
+}
+
+/// Merge the sections of `fragment` into `tree`. Keys of `fragment` replace the ones in `tree`,
+/// other keys of the same section are kept.
+///
+/// # Arguments
+/// * `tree`: The `toml` tree to update.
+/// * `fragment`: The `toml` tree with the new keys.
+fn merge(tree: &mut toml::Table, fragment: toml::Table) {
+    for (section, value) in fragment {
+        let keys = match value {
+            toml::Value::Table(keys) => keys,
+            value => {
+                tree.insert(section, value);
+                continue;
+            }
+        };
+
+        let is_table = match tree.get(&section) {
+            Some(&toml::Value::Table(..)) => true,
+            _ => false
+        };
+        if !is_table {
+            tree.insert(section.clone(), toml::Value::Table(toml::Table::new()));
+        }
+        if let Some(&mut toml::Value::Table(ref mut table)) = tree.get_mut(&section) {
+            for (key, value) in keys {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::env;
     use configuration::client;
     use configuration::dbus;
+    use configuration::policy;
+    use configuration::maintenance;
+    use configuration::vehicle;
+    use test_library::PathPrefix;
+
+    use std::fs;
+    use std::fs::File;
+    use std::io::prelude::*;
 
     #[test]
     fn it_uses_fallbacks_for_its_configuration() {
@@ -113,6 +290,108 @@ mod test {
         assert!(dbus::assert_conf(&configuration.dbus));
     }
 
+    #[test]
+    fn it_parses_the_optional_policy_group() {
+        test_init!();
+        let data = format!("{}\n{}\n{}",
+        client::gen_valid_conf(),
+        dbus::gen_valid_conf(),
+        policy::gen_valid_conf());
+
+        let configuration = Configuration::parse(&data).unwrap();
+        assert!(policy::assert_conf(&configuration.policy));
+    }
+
+    #[test]
+    fn it_parses_the_optional_maintenance_group() {
+        test_init!();
+        let data = format!("{}\n{}\n{}",
+        client::gen_valid_conf(),
+        dbus::gen_valid_conf(),
+        maintenance::gen_valid_conf());
+
+        let configuration = Configuration::parse(&data).unwrap();
+        assert!(maintenance::assert_conf(&configuration.maintenance));
+    }
+
+    #[test]
+    fn it_parses_the_optional_vehicle_group() {
+        test_init!();
+        let data = format!("{}\n{}\n{}",
+        client::gen_valid_conf(),
+        dbus::gen_valid_conf(),
+        vehicle::gen_valid_conf());
+
+        let configuration = Configuration::parse(&data).unwrap();
+        assert!(vehicle::assert_conf(&configuration.vehicle));
+    }
+
+    fn write_conf(prefix: &PathPrefix) -> String {
+        fs::create_dir_all(prefix.to_string()).unwrap();
+        let path = format!("{}/client.toml", prefix);
+        let mut f = File::create(&path).unwrap();
+        f.write_all(format!("{}\n{}", client::gen_valid_conf(), dbus::gen_valid_conf())
+                    .as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn it_applies_and_stores_remote_configuration() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let path = write_conf(&prefix);
+
+        let configuration = Configuration::update_remote(&path, r#"
+        [client]
+        timeout = 120
+        "#).unwrap();
+        assert_eq!(configuration.client.timeout, Some(120));
+        assert_eq!(configuration.client.vin_match, 3);
+
+        Configuration::update_remote(&path, r#"
+        [policy]
+        auto_install = true
+        "#).unwrap();
+        let configuration = Configuration::read(&path).unwrap();
+        assert_eq!(configuration.client.timeout, Some(120));
+        assert!(configuration.policy.auto_install);
+    }
+
+    #[test]
+    fn it_rejects_invalid_remote_configuration() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let path = write_conf(&prefix);
+
+        match Configuration::update_remote(&path, r#"
+        [client]
+        ack_format = "bitmap"
+        "#) {
+            Ok(..) => panic!("Accepted invalid configuration!"),
+            Err(e) => assert_eq!(e, "Unknown ack_format \"bitmap\" in \"client\"".to_string())
+        };
+        assert!(fs::metadata(format!("{}.remote", path)).is_err());
+        assert!(client::assert_conf(&Configuration::read(&path).unwrap().client));
+    }
+
+    #[test]
+    fn it_rejects_remote_changes_requiring_a_restart() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let path = write_conf(&prefix);
+
+        match Configuration::update_remote(&path, r#"
+        [client]
+        timeout = 120
+        storage_dir = "/tmp"
+        "#) {
+            Ok(..) => panic!("Accepted a new storage_dir!"),
+            Err(e) => assert_eq!(e, "Changing \"storage_dir\" in \"client\" requires a restart"
+                                    .to_string())
+        };
+        assert!(fs::metadata(format!("{}.remote", path)).is_err());
+    }
+
     #[test]
     fn it_ignores_extra_keys() {
         test_init!();
@@ -14,7 +14,11 @@ pub struct DBusConfiguration {
     /// The name and interface, where the software loading manager can be reached.
     pub software_manager: String,
     /// Time to wait for installation of a package before it is considered a failure. In seconds.
-    pub timeout: i32 // dbus-rs expects a signed int
+    pub timeout: i32, // dbus-rs expects a signed int
+    /// Whether the software loading manager supports the "NotifyUpdates" method, that carries the
+    /// priority, mandatory flag, deadline and description of updates. Defaults to `false`, which
+    /// sends the plain "Notify" method.
+    pub notify_updates: bool
 }
 
 #[cfg(test)]
@@ -25,7 +29,8 @@ impl DBusConfiguration {
             name: "org.test.test".to_string(),
             interface: "org.test.test".to_string(),
             software_manager: "org.test.software_manager".to_string(),
-            timeout: 20
+            timeout: 20,
+            notify_updates: false
         }
     }
 }
@@ -40,12 +45,14 @@ impl ConfTreeParser<DBusConfiguration> for DBusConfiguration {
                                                      "software_manager",
                                                      "dbus"));
         let timeout = try!(get_optional_key(dbus_tree, "timeout", "dbus"));
+        let notify_updates = try!(get_optional_key(dbus_tree, "notify_updates", "dbus"));
 
         Ok(DBusConfiguration {
             name: name,
             interface: interface,
             software_manager: software_manager,
-            timeout: timeout.unwrap_or(60) * 1000
+            timeout: timeout.unwrap_or(60) * 1000,
+            notify_updates: notify_updates.unwrap_or(false)
         })
     }
 }
@@ -69,6 +76,7 @@ pub fn assert_conf(conf: &DBusConfiguration) -> bool {
     assert_eq!(&conf.name, NAME);
     assert_eq!(&conf.interface, INTERFACE);
     assert_eq!(&conf.software_manager, SOFTWARE_MANAGER);
+    assert!(!conf.notify_updates);
     true
 }
 
@@ -136,4 +144,19 @@ mod test {
             }
         };
     }
+
+    #[test]
+    fn it_parses_the_notify_updates_key() {
+        test_init!();
+        let data = format!(r#"
+        [dbus]
+        name = "{}"
+        interface = "{}"
+        software_manager = "{}"
+        notify_updates = true
+        "#, NAME, INTERFACE, SOFTWARE_MANAGER);
+
+        let tree = read_tree(&data).unwrap();
+        assert!(DBusConfiguration::parse(&tree).unwrap().notify_updates);
+    }
 }
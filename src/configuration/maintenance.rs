@@ -0,0 +1,86 @@
+//! Handles the optional `maintenance` section of the configuration file.
+
+use toml;
+
+use super::common::{ConfTreeParser, Result};
+use super::window::{TimeWindow, get_windows};
+
+/// Type to encode allowed keys for the `maintenance` section of the configuration. Downloads and
+/// installations outside of the configured windows are deferred, until a window opens. Without
+/// windows, they are never deferred.
+#[derive(Clone, Default)]
+pub struct MaintenanceConfiguration {
+    /// [`TimeWindow`](struct.TimeWindow.html)s, in which downloads may be started.
+    pub downloads: Vec<TimeWindow>,
+    /// [`TimeWindow`](struct.TimeWindow.html)s, in which packages may be installed.
+    pub installs: Vec<TimeWindow>
+}
+
+impl ConfTreeParser<MaintenanceConfiguration> for MaintenanceConfiguration {
+    fn parse(tree: &toml::Table) -> Result<MaintenanceConfiguration> {
+        let maintenance_tree = match tree.get("maintenance") {
+            Some(tree) => tree,
+            None => return Ok(MaintenanceConfiguration::default())
+        };
+
+        let downloads = try!(get_windows(maintenance_tree, "downloads", "maintenance"));
+        let installs = try!(get_windows(maintenance_tree, "installs", "maintenance"));
+
+        Ok(MaintenanceConfiguration {
+            downloads: downloads,
+            installs: installs
+        })
+    }
+}
+
+#[cfg(test)] static DOWNLOADS: &'static str = "Mon-Fri 20:00-06:00";
+#[cfg(test)] static INSTALLS: &'static str = "Sat,Sun 02:00-04:00";
+
+#[cfg(test)]
+pub fn gen_valid_conf() -> String {
+    format!(r#"
+    [maintenance]
+    downloads = ["{}", "Sat-Sun 00:00-24:00"]
+    installs = ["{}"]
+    "#, DOWNLOADS, INSTALLS)
+}
+
+#[cfg(test)]
+pub fn assert_conf(conf: &MaintenanceConfiguration) -> bool {
+    assert_eq!(conf.downloads.len(), 2);
+    assert_eq!(conf.downloads[0], DOWNLOADS.parse().unwrap());
+    assert_eq!(conf.installs, vec!(INSTALLS.parse().unwrap()));
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use configuration::common::{ConfTreeParser, read_tree};
+
+    #[test]
+    fn it_doesnt_require_the_maintenance_group() {
+        test_init!();
+        let tree = read_tree("").unwrap();
+        let configuration = MaintenanceConfiguration::parse(&tree).unwrap();
+        assert!(configuration.downloads.is_empty());
+        assert!(configuration.installs.is_empty());
+    }
+
+    #[test]
+    fn it_rejects_invalid_windows() {
+        test_init!();
+        let data = r#"
+        [maintenance]
+        installs = ["Mon-Foo 02:00-04:00"]
+        "#;
+
+        let tree = read_tree(data).unwrap();
+        match MaintenanceConfiguration::parse(&tree) {
+            Ok(..) => panic!("Accepted invalid configuration!"),
+            Err(e) => assert_eq!(e, "Invalid days \"Mon-Foo\" in time window \
+                                     \"Mon-Foo 02:00-04:00\" for \"installs\" in \"maintenance\""
+                                    .to_string())
+        };
+    }
+}
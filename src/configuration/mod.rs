@@ -6,7 +6,15 @@ mod configuration;
 mod common;
 mod client;
 mod dbus;
+mod policy;
+mod maintenance;
+mod vehicle;
+mod window;
 
 pub use self::configuration::Configuration;
 pub use self::client::ClientConfiguration;
 pub use self::dbus::DBusConfiguration;
+pub use self::policy::PolicyConfiguration;
+pub use self::maintenance::MaintenanceConfiguration;
+pub use self::vehicle::VehicleConfiguration;
+pub use self::window::{TimeWindow, any_open};
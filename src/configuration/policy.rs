@@ -0,0 +1,112 @@
+//! Handles the optional `policy` section of the configuration file.
+
+use toml;
+
+use super::common::{get_optional_key, ConfTreeParser, Result};
+use super::window::{TimeWindow, get_windows};
+
+/// Type to encode allowed keys for the `policy` section of the configuration. All keys are
+/// optional, without the section updates are only downloaded on request of the user.
+#[derive(Clone, Default)]
+pub struct PolicyConfiguration {
+    /// Download and install updates matching the rules below, without waiting for the user.
+    pub auto_install: bool,
+    /// Match all mandatory updates.
+    pub mandatory: bool,
+    /// Match updates by name. Patterns may contain `*` as a wildcard.
+    pub packages: Vec<String>,
+    /// Only match updates up to this size in bytes.
+    pub max_size: Option<i64>,
+    /// Only match updates during one of these [`TimeWindow`](struct.TimeWindow.html)s. Updates
+    /// match at any time, if the list is empty.
+    pub windows: Vec<TimeWindow>
+}
+
+#[cfg(test)]
+impl PolicyConfiguration {
+    /// Generate a test configuration.
+    pub fn gen_test() -> PolicyConfiguration {
+        PolicyConfiguration::default()
+    }
+}
+
+impl ConfTreeParser<PolicyConfiguration> for PolicyConfiguration {
+    fn parse(tree: &toml::Table) -> Result<PolicyConfiguration> {
+        let policy_tree = match tree.get("policy") {
+            Some(tree) => tree,
+            None => return Ok(PolicyConfiguration::default())
+        };
+
+        let auto_install = try!(get_optional_key(policy_tree, "auto_install", "policy"));
+        let mandatory = try!(get_optional_key(policy_tree, "mandatory", "policy"));
+        let packages = try!(get_optional_key(policy_tree, "packages", "policy"));
+        let max_size = try!(get_optional_key(policy_tree, "max_size", "policy"));
+        let windows = try!(get_windows(policy_tree, "windows", "policy"));
+
+        Ok(PolicyConfiguration {
+            auto_install: auto_install.unwrap_or(false),
+            mandatory: mandatory.unwrap_or(false),
+            packages: packages.unwrap_or(Vec::new()),
+            max_size: max_size,
+            windows: windows
+        })
+    }
+}
+
+#[cfg(test)] static PACKAGES: &'static str = "telematics-*";
+#[cfg(test)] static MAX_SIZE: i64 = 10485760;
+#[cfg(test)] static WINDOW: &'static str = "22:00-05:30";
+#[cfg(test)] static WEEKEND_WINDOW: &'static str = "Sat,Sun 10:00-16:00";
+
+#[cfg(test)]
+pub fn gen_valid_conf() -> String {
+    format!(r#"
+    [policy]
+    auto_install = true
+    mandatory = true
+    packages = ["{}"]
+    max_size = {}
+    windows = ["{}", "{}"]
+    "#, PACKAGES, MAX_SIZE, WINDOW, WEEKEND_WINDOW)
+}
+
+#[cfg(test)]
+pub fn assert_conf(conf: &PolicyConfiguration) -> bool {
+    assert!(conf.auto_install);
+    assert!(conf.mandatory);
+    assert_eq!(conf.packages, vec!(PACKAGES.to_string()));
+    assert_eq!(conf.max_size, Some(MAX_SIZE));
+    assert_eq!(conf.windows, vec!(WINDOW.parse().unwrap(), WEEKEND_WINDOW.parse().unwrap()));
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use configuration::common::{ConfTreeParser, read_tree};
+
+    #[test]
+    fn it_doesnt_require_the_policy_group() {
+        test_init!();
+        let tree = read_tree("").unwrap();
+        let configuration = PolicyConfiguration::parse(&tree).unwrap();
+        assert!(!configuration.auto_install);
+        assert!(configuration.packages.is_empty());
+    }
+
+    #[test]
+    fn it_rejects_invalid_time_windows() {
+        test_init!();
+        let data = r#"
+        [policy]
+        windows = ["22:00-25:00"]
+        "#;
+
+        let tree = read_tree(data).unwrap();
+        match PolicyConfiguration::parse(&tree) {
+            Ok(..) => panic!("Accepted invalid configuration!"),
+            Err(e) => assert_eq!(e, "Invalid time \"25:00\" in time window \"22:00-25:00\" \
+                                     for \"windows\" in \"policy\"".to_string())
+        };
+    }
+}
@@ -0,0 +1,126 @@
+//! Handles the optional `vehicle` section of the configuration file.
+
+use toml;
+
+use super::common::{get_optional_key, ConfTreeParser, Result};
+
+/// Type to encode allowed keys for the `vehicle` section of the configuration. Holds the
+/// preconditions, the vehicle has to meet before packages are installed. Without the section,
+/// packages are installed regardless of the vehicle state.
+#[derive(Clone, Default)]
+pub struct VehicleConfiguration {
+    /// Local command checking the vehicle state. Installation is postponed, if it exits with a
+    /// non-zero status. The first line of its output is reported as reason.
+    pub command: Option<String>,
+    /// D-Bus name and interface of the service providing the `Ignition`, `BatteryLevel` and
+    /// `ParkingBrake` properties.
+    pub state_service: Option<String>,
+    /// Require the ignition to be off.
+    pub ignition_off: bool,
+    /// Require the battery level to be at least this many percent.
+    pub min_battery: Option<i64>,
+    /// Require the parking brake to be engaged.
+    pub parking_brake: bool,
+    /// Seconds to wait before checking the preconditions of a postponed installation again.
+    pub retry_interval: Option<i64>
+}
+
+#[cfg(test)]
+impl VehicleConfiguration {
+    /// Generate a test configuration.
+    pub fn gen_test() -> VehicleConfiguration {
+        VehicleConfiguration::default()
+    }
+}
+
+impl ConfTreeParser<VehicleConfiguration> for VehicleConfiguration {
+    fn parse(tree: &toml::Table) -> Result<VehicleConfiguration> {
+        let vehicle_tree = match tree.get("vehicle") {
+            Some(tree) => tree,
+            None => return Ok(VehicleConfiguration::default())
+        };
+
+        let command = try!(get_optional_key(vehicle_tree, "command", "vehicle"));
+        let state_service = try!(get_optional_key(vehicle_tree, "state_service", "vehicle"));
+        let ignition_off = try!(get_optional_key(vehicle_tree, "ignition_off", "vehicle"));
+        let min_battery = try!(get_optional_key(vehicle_tree, "min_battery", "vehicle"));
+        let parking_brake = try!(get_optional_key(vehicle_tree, "parking_brake", "vehicle"));
+        let retry_interval = try!(get_optional_key(vehicle_tree, "retry_interval", "vehicle"));
+
+        let ignition_off = ignition_off.unwrap_or(false);
+        let parking_brake = parking_brake.unwrap_or(false);
+        if state_service.is_none() && (ignition_off || parking_brake || min_battery.is_some()) {
+            return Err("Key \"state_service\" in \"vehicle\" is required to check the vehicle \
+                        state".to_string());
+        }
+
+        Ok(VehicleConfiguration {
+            command: command,
+            state_service: state_service,
+            ignition_off: ignition_off,
+            min_battery: min_battery,
+            parking_brake: parking_brake,
+            retry_interval: retry_interval
+        })
+    }
+}
+
+#[cfg(test)] static COMMAND: &'static str = "/usr/libexec/sota/check-vehicle";
+#[cfg(test)] static STATE_SERVICE: &'static str = "org.genivi.vehicle_state";
+#[cfg(test)] static MIN_BATTERY: i64 = 30;
+#[cfg(test)] static RETRY_INTERVAL: i64 = 300;
+
+#[cfg(test)]
+pub fn gen_valid_conf() -> String {
+    format!(r#"
+    [vehicle]
+    command = "{}"
+    state_service = "{}"
+    ignition_off = true
+    min_battery = {}
+    parking_brake = true
+    retry_interval = {}
+    "#, COMMAND, STATE_SERVICE, MIN_BATTERY, RETRY_INTERVAL)
+}
+
+#[cfg(test)]
+pub fn assert_conf(conf: &VehicleConfiguration) -> bool {
+    assert_eq!(&conf.command.clone().unwrap(), COMMAND);
+    assert_eq!(&conf.state_service.clone().unwrap(), STATE_SERVICE);
+    assert!(conf.ignition_off);
+    assert_eq!(conf.min_battery, Some(MIN_BATTERY));
+    assert!(conf.parking_brake);
+    assert_eq!(conf.retry_interval, Some(RETRY_INTERVAL));
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use configuration::common::{ConfTreeParser, read_tree};
+
+    #[test]
+    fn it_doesnt_require_the_vehicle_group() {
+        test_init!();
+        let tree = read_tree("").unwrap();
+        let configuration = VehicleConfiguration::parse(&tree).unwrap();
+        assert!(configuration.command.is_none());
+        assert!(!configuration.ignition_off);
+    }
+
+    #[test]
+    fn it_requires_the_state_service_for_state_checks() {
+        test_init!();
+        let data = r#"
+        [vehicle]
+        min_battery = 30
+        "#;
+
+        let tree = read_tree(data).unwrap();
+        match VehicleConfiguration::parse(&tree) {
+            Ok(..) => panic!("Accepted invalid configuration!"),
+            Err(e) => assert_eq!(e, "Key \"state_service\" in \"vehicle\" is required to check \
+                                     the vehicle state".to_string())
+        };
+    }
+}
@@ -0,0 +1,228 @@
+//! Time windows, used by several sections of the configuration file.
+
+use std::str::FromStr;
+
+use time::Tm;
+use toml;
+
+use super::common::{get_optional_key, Result};
+
+/// Bitmask of all days of the week, with Sunday as bit `0`, matching `Tm::tm_wday`.
+const ALL_DAYS: u8 = 0x7f;
+
+/// Abbreviated names of the days of the week, starting with Sunday.
+const DAY_NAMES: [&'static str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Type for a recurring time window in local time, given as `[DAYS] HH:MM-HH:MM`. `DAYS` is a comma
+/// separated list of days or day ranges, e.g. `Mon-Fri,Sun`, and defaults to every day.
+///
+/// Windows ending before they start wrap around midnight and belong to the day they start on, i.e.
+/// `Fri 22:00-06:00` ends on Saturday morning. Windows starting and ending at the same time span
+/// the whole day.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeWindow {
+    /// Days the window starts on, as bitmask with Sunday as bit `0`.
+    pub days: u8,
+    /// Start of the window in minutes after midnight, inclusive.
+    pub start: u32,
+    /// End of the window in minutes after midnight, exclusive.
+    pub end: u32
+}
+
+impl TimeWindow {
+    /// Check whether `tm` lies within this window.
+    ///
+    /// # Arguments
+    /// * `tm`: The local time to check.
+    pub fn contains(&self, tm: &Tm) -> bool {
+        let minute = (tm.tm_hour * 60 + tm.tm_min) as u32;
+        let today = self.days & (1 << tm.tm_wday) != 0;
+        let yesterday = self.days & (1 << ((tm.tm_wday + 6) % 7)) != 0;
+
+        if self.start < self.end {
+            today && self.start <= minute && minute < self.end
+        } else if self.start > self.end {
+            (today && self.start <= minute) || (yesterday && minute < self.end)
+        } else {
+            today
+        }
+    }
+}
+
+/// Parse a `HH:MM` time of day to minutes after midnight. `24:00` is accepted as the end of the
+/// day.
+///
+/// # Arguments
+/// * `s`: The time of day to parse.
+fn parse_time(s: &str) -> Option<u32> {
+    let mut parts = s.trim().splitn(2, ':');
+    let hours = parts.next().and_then(|h| h.parse::<u32>().ok());
+    let minutes = parts.next().and_then(|m| m.parse::<u32>().ok());
+    match (hours, minutes) {
+        (Some(24), Some(0)) => Some(24 * 60),
+        (Some(h), Some(m)) if h < 24 && m < 60 => Some(h * 60 + m),
+        _ => None
+    }
+}
+
+/// Parse the abbreviated name of a day to its index, starting with Sunday.
+///
+/// # Arguments
+/// * `s`: The name of the day, e.g. `Mon`.
+fn parse_day(s: &str) -> Option<u32> {
+    let s = s.trim().to_lowercase();
+    DAY_NAMES.iter().position(|d| *d == s).map(|i| i as u32)
+}
+
+/// Parse a comma separated list of days and day ranges to a bitmask. Ranges may wrap around the
+/// end of the week, e.g. `Fri-Mon`.
+///
+/// # Arguments
+/// * `s`: The list of days to parse.
+fn parse_days(s: &str) -> Option<u8> {
+    let mut days = 0;
+    for item in s.split(',') {
+        let mut range = item.splitn(2, '-');
+        let first = match range.next().and_then(parse_day) {
+            Some(day) => day,
+            None => return None
+        };
+        let last = match range.next() {
+            Some(day) => match parse_day(day) {
+                Some(day) => day,
+                None => return None
+            },
+            None => first
+        };
+
+        let mut day = first;
+        loop {
+            days |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeWindow> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (days, times) = match parts.len() {
+            1 => (ALL_DAYS, parts[0]),
+            2 => (try!(parse_days(parts[0])
+                       .ok_or(format!("Invalid days \"{}\" in time window \"{}\"",
+                                      parts[0], s))),
+                  parts[1]),
+            _ => return Err(format!("Invalid time window \"{}\", expected [DAYS] HH:MM-HH:MM",
+                                    s))
+        };
+
+        let times: Vec<&str> = times.splitn(2, '-').collect();
+        if times.len() != 2 {
+            return Err(format!("Invalid time window \"{}\", expected [DAYS] HH:MM-HH:MM", s));
+        }
+        let start = try!(parse_time(times[0])
+                         .ok_or(format!("Invalid time \"{}\" in time window \"{}\"",
+                                        times[0], s)));
+        let end = try!(parse_time(times[1])
+                       .ok_or(format!("Invalid time \"{}\" in time window \"{}\"",
+                                      times[1], s)));
+
+        Ok(TimeWindow {
+            days: days,
+            start: start % (24 * 60),
+            end: end % (24 * 60)
+        })
+    }
+}
+
+/// Parse a optional list of time windows, returning a empty list if the key can't be found.
+///
+/// # Arguments
+/// * `subtree`: The `toml` tree to parse.
+/// * `key`: The key to look for.
+/// * `group`: The section of the configuration, used in error messages.
+pub fn get_windows(subtree: &toml::Value, key: &str, group: &str) -> Result<Vec<TimeWindow>> {
+    let windows: Option<Vec<String>> = try!(get_optional_key(subtree, key, group));
+    windows.unwrap_or(Vec::new()).iter()
+        .map(|w| w.parse::<TimeWindow>()
+             .map_err(|e| format!("{} for \"{}\" in \"{}\"", e, key, group)))
+        .collect()
+}
+
+/// Check whether any of `windows` contains `tm`. An empty list of windows is always open.
+///
+/// # Arguments
+/// * `windows`: The windows to check.
+/// * `tm`: The local time to check.
+pub fn any_open(windows: &[TimeWindow], tm: &Tm) -> bool {
+    windows.is_empty() || windows.iter().any(|w| w.contains(tm))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use time;
+
+    /// Create a `Tm` on the given day of the week, with Sunday as `0`.
+    fn at(wday: i32, hour: i32, min: i32) -> time::Tm {
+        let mut tm = time::empty_tm();
+        tm.tm_wday = wday;
+        tm.tm_hour = hour;
+        tm.tm_min = min;
+        tm
+    }
+
+    #[test]
+    fn it_checks_daily_windows() {
+        test_init!();
+        let day: TimeWindow = "08:00-17:00".parse().unwrap();
+        assert!(day.contains(&at(1, 8, 0)));
+        assert!(!day.contains(&at(1, 17, 0)));
+
+        let night: TimeWindow = "22:00-05:30".parse().unwrap();
+        assert!(night.contains(&at(3, 23, 59)));
+        assert!(night.contains(&at(3, 5, 29)));
+        assert!(!night.contains(&at(3, 12, 0)));
+
+        let always: TimeWindow = "00:00-24:00".parse().unwrap();
+        assert!(always.contains(&at(0, 12, 0)));
+    }
+
+    #[test]
+    fn it_checks_weekday_windows() {
+        test_init!();
+        let weekdays: TimeWindow = "Mon-Fri 09:00-17:00".parse().unwrap();
+        assert!(weekdays.contains(&at(1, 9, 0)));
+        assert!(!weekdays.contains(&at(6, 9, 0)));
+
+        let weekend: TimeWindow = "Fri-Sun,Wed 22:00-06:00".parse().unwrap();
+        assert!(weekend.contains(&at(5, 23, 0)));
+        assert!(weekend.contains(&at(1, 5, 0)));
+        assert!(!weekend.contains(&at(1, 23, 0)));
+        assert!(weekend.contains(&at(4, 5, 0)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_windows() {
+        test_init!();
+        assert!("22:00".parse::<TimeWindow>().is_err());
+        assert!("22:00-25:00".parse::<TimeWindow>().is_err());
+        assert!("Mon-Foo 22:00-23:00".parse::<TimeWindow>().is_err());
+        assert!("Mon 22:00-23:00 extra".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn it_treats_no_windows_as_always_open() {
+        test_init!();
+        assert!(any_open(&[], &at(1, 12, 0)));
+        let windows: [TimeWindow; 1] = ["Sat 00:00-24:00".parse().unwrap()];
+        assert!(!any_open(&windows, &at(1, 12, 0)));
+    }
+}
@@ -0,0 +1,143 @@
+//! Reconstruction of packages from binary deltas against a base package.
+//!
+//! The delta format follows bsdiff, without the compression of the individual streams. A delta
+//! starts with the magic `SOTADIFF` and the size of the new package, followed by control blocks.
+//! Each block consists of the three 64 bit little endian integers `add`, `copy` and `seek`, then
+//! `add` bytes, that are added bytewise to the base at the current base offset, and `copy` bytes,
+//! that are inserted verbatim. Afterwards the base offset is moved by `add` and the signed `seek`.
+
+/// Magic bytes at the beginning of every delta.
+const MAGIC: &'static [u8] = b"SOTADIFF";
+
+/// Apply `delta` to `base` and return the reconstructed package. Returns a `String` with a error
+/// message, if the delta is malformed or doesn't fit the base.
+///
+/// # Arguments
+/// * `base`: The data of the base package.
+/// * `delta`: The delta to apply.
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    if delta.len() < 16 || &delta[..8] != MAGIC {
+        return Err("Invalid delta header".to_string());
+    }
+
+    let size = try!(read_u64(delta, 8));
+    let mut pos = 16;
+    let mut base_pos: i64 = 0;
+    let mut package = Vec::new();
+
+    while (package.len() as u64) < size {
+        let add = try!(read_u64(delta, pos));
+        let copy = try!(read_u64(delta, pos + 8));
+        let seek = try!(read_u64(delta, pos + 16)) as i64;
+        pos += 24;
+
+        let diff = try!(read_bytes(delta, pos, add));
+        pos += diff.len();
+        for (i, b) in diff.iter().enumerate() {
+            let index = base_pos.checked_add(i as i64).unwrap_or(-1);
+            if index < 0 || index >= base.len() as i64 {
+                return Err("Delta reads outside of the base package".to_string());
+            }
+            package.push(base[index as usize].wrapping_add(*b));
+        }
+
+        let extra = try!(read_bytes(delta, pos, copy));
+        pos += extra.len();
+        package.extend_from_slice(extra);
+
+        base_pos = try!(base_pos.checked_add(add as i64)
+                        .and_then(|p| p.checked_add(seek))
+                        .ok_or("Invalid seek in delta".to_string()));
+    }
+
+    if package.len() as u64 != size {
+        return Err(format!("Delta produced {} bytes, expected {}", package.len(), size));
+    }
+    if pos != delta.len() {
+        return Err("Trailing data after delta".to_string());
+    }
+    Ok(package)
+}
+
+/// Read a little endian `u64` from `data` at `pos`.
+///
+/// # Arguments
+/// * `data`: The data to read from.
+/// * `pos`: Offset of the integer.
+fn read_u64(data: &[u8], pos: usize) -> Result<u64, String> {
+    let bytes = try!(read_bytes(data, pos, 8));
+    Ok(bytes.iter().rev().fold(0, |n, b| (n << 8) | *b as u64))
+}
+
+/// Returns the `len` bytes of `data` starting at `pos` or a `String` with a error message, if the
+/// delta is truncated.
+///
+/// # Arguments
+/// * `data`: The data to read from.
+/// * `pos`: Offset of the first byte.
+/// * `len`: Number of bytes to read.
+fn read_bytes(data: &[u8], pos: usize, len: u64) -> Result<&[u8], String> {
+    if pos > data.len() || len > (data.len() - pos) as u64 {
+        return Err("Delta is truncated".to_string());
+    }
+    Ok(&data[pos..pos + len as usize])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_u64(delta: &mut Vec<u8>, n: u64) {
+        for i in 0..8 {
+            delta.push((n >> (i * 8)) as u8);
+        }
+    }
+
+    fn block(diff: &[u8], extra: &[u8], seek: i64) -> Vec<u8> {
+        let mut block = Vec::new();
+        push_u64(&mut block, diff.len() as u64);
+        push_u64(&mut block, extra.len() as u64);
+        push_u64(&mut block, seek as u64);
+        block.extend_from_slice(diff);
+        block.extend_from_slice(extra);
+        block
+    }
+
+    fn gen_delta(size: u64, blocks: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut delta = b"SOTADIFF".to_vec();
+        push_u64(&mut delta, size);
+        for block in blocks {
+            delta.extend_from_slice(&block);
+        }
+        delta
+    }
+
+    #[test]
+    fn it_reconstructs_the_package_from_the_base() {
+        test_init!();
+        let delta = gen_delta(15, vec!(block(&[0; 6], b"new ", 0), block(&[0; 5], b"", 0)));
+        assert_eq!(apply(b"hello world", &delta).unwrap(), b"hello new world".to_vec());
+    }
+
+    #[test]
+    fn it_adds_the_diff_bytewise_and_seeks_in_the_base() {
+        test_init!();
+        let delta = gen_delta(4, vec!(block(&[1, 1], b"", 2), block(&[0, 0], b"", 0)));
+        assert_eq!(apply(b"abcdef", &delta).unwrap(), b"bcef".to_vec());
+    }
+
+    #[test]
+    fn it_rejects_malformed_deltas() {
+        test_init!();
+        let base = b"abcdef";
+        assert!(apply(base, b"NOTADIFF").is_err());
+        assert!(apply(base, &gen_delta(10, vec!(block(&[0, 0], b"", 0)))).is_err());
+        assert!(apply(base, &gen_delta(3, vec!(block(&[0, 0], b"", -10),
+                                               block(&[0], b"", 0)))).is_err());
+        assert!(apply(base, &gen_delta(8, vec!(block(&[0; 8], b"", 0)))).is_err());
+
+        let mut trailing = gen_delta(1, vec!(block(b"", b"x", 0)));
+        trailing.push(0);
+        assert!(apply(base, &trailing).is_err());
+    }
+}
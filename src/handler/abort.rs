@@ -3,6 +3,7 @@
 use std::sync::Mutex;
 use message::{BackendServices, Notification};
 use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
 
 /// Type for "Abort Transfer" messages.
 #[derive(RustcDecodable)]
@@ -13,9 +14,10 @@ impl HandleMessageParams for AbortParams {
     fn handle(&self,
               _: &Mutex<BackendServices>,
               transfers: &Mutex<Transfers>,
-              _: &str, _: &str, _: &str) -> bool {
+              _: &str, _: &str, _: &ClientConfiguration) -> bool {
         let mut transfers = transfers.lock().unwrap();
-        transfers.clear();
+        transfers.active.clear();
+        transfers.queued.clear();
         true
     }
 
@@ -27,11 +29,11 @@ mod test {
     use super::*;
     use test_library::*;
 
-    use std::sync::Mutex;
-    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
 
-    use handler::HandleMessageParams;
-    use persistence::Transfer;
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
+    use persistence::{Transfer, MemoryStorage};
 
     #[test]
     fn it_removes_a_single_transfer() {
@@ -39,33 +41,33 @@ mod test {
 
         let services = Mutex::new(get_empty_backend());
 
-        let prefix = PathPrefix::new();
-        let mut transfer = Transfer::new_test(&prefix);
+        let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
         let package = transfer.randomize(10);
 
-        let transfers = Mutex::new(HashMap::new());
-        transfers.lock().unwrap().insert(package.clone(), transfer);
+        let transfers = Mutex::new(Transfers::new_test());
+        transfers.lock().unwrap().active.insert(package.clone(), transfer);
 
         let abort = AbortParams;
-        assert!(abort.handle(&services, &transfers, "", "", ""));
-        assert!(transfers.lock().unwrap().is_empty());
+        let conf = ClientConfiguration::gen_test();
+        assert!(abort.handle(&services, &transfers, "", "", &conf));
+        assert!(transfers.lock().unwrap().active.is_empty());
     }
 
     #[test]
     fn it_removes_all_transfers() {
         test_init!();
         let services = Mutex::new(get_empty_backend());
-        let prefix = PathPrefix::new();
 
-        let transfers = Mutex::new(HashMap::new());
+        let transfers = Mutex::new(Transfers::new_test());
         for i in 1..20 {
-            let mut transfer = Transfer::new_test(&prefix);
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
             let package = transfer.randomize(i);
-            transfers.lock().unwrap().insert(package, transfer);
+            transfers.lock().unwrap().active.insert(package, transfer);
         }
 
         let abort = AbortParams;
-        assert!(abort.handle(&services, &transfers, "", "", ""));
-        assert!(transfers.lock().unwrap().is_empty());
+        let conf = ClientConfiguration::gen_test();
+        assert!(abort.handle(&services, &transfers, "", "", &conf));
+        assert!(transfers.lock().unwrap().active.is_empty());
     }
 }
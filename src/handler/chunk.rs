@@ -1,11 +1,18 @@
 //! Handles messages transferring single chunks.
 
+use std::cmp;
 use std::sync::Mutex;
 
+use time;
+
 #[cfg(not(test))] use rvi::send_message;
+#[cfg(test)] use rustc_serialize::Encodable;
 
-use message::{BackendServices, PackageId, ChunkReceived, Notification};
-use handler::{Transfers, HandleMessageParams};
+use message::{BackendServices, PackageId, ChunkReceived, ChunkRejected, Notification};
+use message::ServerPackageReport;
+use handler::{Transfers, HandleMessageParams, start_queued};
+use configuration::ClientConfiguration;
+use persistence::{Transfer, ChunkError};
 
 /// Type for messages transferring single chunks.
 #[derive(RustcDecodable)]
@@ -15,51 +22,135 @@ pub struct ChunkParams {
     /// The index of this chunk.
     pub index: u64,
     /// The package transfer this chunk belongs to.
-    pub package: PackageId
+    pub package: PackageId,
+    /// The SHA1 checksum of the decoded data. Chunks not matching it are rejected, so they can be
+    /// retransmitted.
+    pub checksum: Option<String>
 }
 
 impl HandleMessageParams for ChunkParams {
     fn handle(&self,
               services: &Mutex<BackendServices>,
               transfers: &Mutex<Transfers>,
-              rvi_url: &str, vin: &str, _: &str) -> bool {
+              rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
         let services = services.lock().unwrap();
         let mut transfers = transfers.lock().unwrap();
-        transfers.get_mut(&self.package).map(|t| {
-            if t.write_chunk(&self.bytes, self.index) {
-                info!("Wrote chunk {} for package {}", self.index, self.package);
+        let failure = match transfers.active.get_mut(&self.package) {
+            Some(t) => {
+                let checksum = self.checksum.as_ref().map(|c| &c[..]);
+                match t.write_chunk(&self.bytes, self.index, checksum) {
+                    Ok(()) => {
+                        info!("Wrote chunk {} for package {}", self.index, self.package);
+                        t.unacked += 1;
+                        if ack_due(t, conf, time::get_time().sec) {
+                            return acknowledge(t, &services, rvi_url, vin, conf);
+                        }
+                        return true;
+                    },
+                    Err(e) => e
+                }
+            },
+            None => {
+                error!("Couldn't find transfer for package {}", self.package);
+                return false;
+            }
+        };
+
+        // Corrupted chunks are rejected, so the server retransmits them. Chunks, that can't be
+        // stored, fail the whole transfer instead, as retransmitting them won't help.
+        match failure {
+            ChunkError::Corrupted(e) => {
+                error!("{}", e);
                 try_or!(send_message(rvi_url,
-                                     ChunkReceived {
+                                     ChunkRejected {
                                          package: self.package.clone(),
-                                         chunks: t.transferred_chunks.clone(),
+                                         index: self.index,
                                          vin: vin.to_string()
                                      },
                                      &services.ack), return false);
-                true
-            } else {
-                false
+            },
+            ChunkError::Storage(e) => {
+                error!("{}", e);
+                transfers.fail(&self.package, &e);
+                try_or!(send_message(rvi_url,
+                                     ServerPackageReport {
+                                         package: self.package.clone(),
+                                         status: false,
+                                         description: e,
+                                         vin: vin.to_string(),
+                                         rollback: None,
+                                         result: None
+                                     },
+                                     &services.report), {});
+                start_queued(&services, &mut transfers, rvi_url, vin, conf);
             }
-        }).unwrap_or_else(|| {
-            error!("Couldn't find transfer for package {}", self.package);
-            false
-        })
+        }
+        false
     }
 
     fn get_message(&self) -> Option<Notification> { None }
 }
 
+/// Check whether the chunks `transfer` received since the last acknowledgement should be
+/// acknowledged at `now`. That is the case, once `ack_batch` chunks are pending, the server
+/// reached the end of its window, `ack_interval` seconds passed or the transfer is complete.
+///
+/// The window covers the `ack_window` chunks starting at the first chunk, that was missing at the
+/// last acknowledgement. It is used up, once the last chunk of the window was received, even if
+/// chunks before it are still missing, as the server can't send any further chunks until then.
+///
+/// # Arguments
+/// * `transfer`: The `Transfer` to check.
+/// * `conf`: The `client` section of the configuration.
+/// * `now`: The current time as a unix epoch timestamp.
+pub fn ack_due(transfer: &Transfer, conf: &ClientConfiguration, now: i64) -> bool {
+    if transfer.unacked == 0 {
+        return false;
+    }
+
+    let batch = conf.ack_batch.map(|b| cmp::max(b, 1) as u64).unwrap_or(1);
+    let window_used = conf.ack_window.map(|w| {
+        let end = transfer.window_start + cmp::max(w, 1) as u64;
+        transfer.transferred_chunks.last().map(|&last| last + 1 >= end).unwrap_or(false)
+    }).unwrap_or(false);
+    let interval_passed = conf.ack_interval.map(|i| now - transfer.last_ack >= i).unwrap_or(false);
+
+    transfer.unacked >= batch || window_used || interval_passed || transfer.is_complete()
+}
+
+/// Acknowledge all chunks `transfer` received so far. Returns `false`, if the acknowledgement
+/// couldn't be sent.
+///
+/// # Arguments
+/// * `transfer`: The `Transfer` to acknowledge.
+/// * `services`: The service URLs of the server.
+/// * `rvi_url`: The URL, where RVI can be found.
+/// * `vin`: The VIN of this device.
+/// * `conf`: The `client` section of the configuration.
+pub fn acknowledge(transfer: &mut Transfer, services: &BackendServices,
+                   rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
+    let ack = ChunkReceived::new(transfer.package.clone(), &transfer.transferred_chunks,
+                                 vin, conf);
+    transfer.unacked = 0;
+    transfer.last_ack = time::get_time().sec;
+    transfer.window_start = transfer.first_missing();
+
+    try_or!(send_message(rvi_url, ack, &services.ack), return false);
+    true
+}
+
 #[cfg(test)]
-fn send_message(url: &str, chunks: ChunkReceived, ack: &str)
+fn send_message<E: Encodable>(url: &str, _: E, ack: &str)
     -> Result<bool, bool> {
-    trace!("Would send received indices for {}, to {} on {}",
-           chunks.package, ack, url);
+    trace!("Would send chunk acknowledgement to {} on {}", ack, url);
     Ok(true)
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
-    use std::sync::Mutex;
+    use std::fs;
+    use std::fs::File;
+    use std::sync::{Arc, Mutex};
 
     use super::*;
     use test_library::*;
@@ -69,9 +160,10 @@ mod test {
     use rustc_serialize::base64;
     use rustc_serialize::base64::ToBase64;
 
-    use handler::HandleMessageParams;
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
     use message::{BackendServices, PackageId};
-    use persistence::Transfer;
+    use persistence::{Transfer, FileStorage, MemoryStorage};
 
     trait Tester<T> { fn new_test(i: usize, package: PackageId) -> T; }
 
@@ -90,7 +182,8 @@ mod test {
             ChunkParams {
                 bytes: b64_msg,
                 index: i as u64,
-                package: package
+                package: package,
+                checksum: None
             }
         }
     }
@@ -99,15 +192,15 @@ mod test {
     fn it_returns_true_for_existing_transfers() {
         test_init!();
         for i in 1..20 {
-            let prefix = PathPrefix::new();
-            let mut transfer = Transfer::new_test(&prefix);
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
             let package = transfer.randomize(i);
-            let transfers = Mutex::new(HashMap::new());
-            transfers.lock().unwrap().insert(package.clone(), transfer);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
             let chunk = ChunkParams::new_test(i, package);
-            assert!(chunk.handle(&services, &transfers, "ignored", "", ""));
+            assert!(chunk.handle(&services, &transfers, "ignored", "", &conf));
         }
     }
 
@@ -116,11 +209,125 @@ mod test {
         test_init!();
         for i in 1..20 {
             let package = generate_random_package(i);
-            let transfers = Mutex::new(HashMap::new());
+            let transfers = Mutex::new(Transfers::new_test());
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
             let chunk = ChunkParams::new_test(i, package);
-            assert!(!chunk.handle(&services, &transfers, "ignored", "", ""));
+            assert!(!chunk.handle(&services, &transfers, "ignored", "", &conf));
+        }
+    }
+
+    #[test]
+    fn it_rejects_corrupted_chunks() {
+        test_init!();
+        for i in 1..20 {
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+            let package = transfer.randomize(i);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
+            let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
+
+            let mut chunk = ChunkParams::new_test(i, package.clone());
+            chunk.checksum = Some("fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca".to_string());
+            assert!(!chunk.handle(&services, &transfers, "ignored", "", &conf));
+            let transfers = transfers.lock().unwrap();
+            assert!(transfers.active.get(&package).unwrap().transferred_chunks.is_empty());
         }
     }
+
+    #[test]
+    fn it_fails_the_transfer_if_chunks_cant_be_stored() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        fs::create_dir_all(prefix.to_string()).unwrap();
+        File::create(format!("{}/downloads", prefix)).unwrap();
+        let mut transfer = Transfer::new_test(Arc::new(FileStorage::new(&prefix.to_string())));
+        let package = transfer.randomize(10);
+        let transfers = Mutex::new(Transfers::new_test());
+        transfers.lock().unwrap().active.insert(package.clone(), transfer);
+        let services = Mutex::new(BackendServices::new());
+        let conf = ClientConfiguration::gen_test();
+
+        let chunk = ChunkParams::new_test(10, package.clone());
+        assert!(!chunk.handle(&services, &transfers, "ignored", "", &conf));
+        let transfers = transfers.lock().unwrap();
+        assert!(transfers.active.get(&package).is_none());
+        assert_eq!(transfers.failed.len(), 1);
+        assert_eq!(transfers.failed[0].0, package);
+    }
+
+    #[test]
+    fn it_batches_acknowledgements() {
+        test_init!();
+        let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+        let package = transfer.randomize(10);
+        let transfers = Mutex::new(Transfers::new_test());
+        transfers.lock().unwrap().active.insert(package.clone(), transfer);
+        let services = Mutex::new(BackendServices::new());
+        let mut conf = ClientConfiguration::gen_test();
+        conf.ack_batch = Some(3);
+
+        for (i, pending) in [1, 2, 0, 1, 2, 0].iter().enumerate() {
+            let chunk = ChunkParams::new_test(i + 1, package.clone());
+            assert!(chunk.handle(&services, &transfers, "ignored", "", &conf));
+            assert_eq!(transfers.lock().unwrap().active.get(&package).unwrap().unacked,
+                       *pending);
+        }
+    }
+
+    #[test]
+    fn it_acknowledges_pending_chunks_when_due() {
+        test_init!();
+        let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+        let mut conf = ClientConfiguration::gen_test();
+        conf.ack_batch = Some(10);
+        conf.ack_interval = Some(5);
+        let now = transfer.last_ack;
+
+        assert!(!ack_due(&transfer, &conf, now + 10));
+        transfer.unacked = 2;
+        assert!(!ack_due(&transfer, &conf, now));
+        assert!(ack_due(&transfer, &conf, now + 5));
+
+        transfer.chunkscount = 2;
+        transfer.transferred_chunks = vec!(0, 1);
+        assert!(ack_due(&transfer, &conf, now));
+    }
+
+    #[test]
+    fn it_acknowledges_once_the_window_is_used_up() {
+        test_init!();
+        let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+        let services = BackendServices::new();
+        let mut conf = ClientConfiguration::gen_test();
+        conf.ack_batch = Some(10);
+        conf.ack_window = Some(4);
+        let now = transfer.last_ack;
+
+        // chunk 0 is missing, the window ends with chunk 3
+        transfer.transferred_chunks = vec!(1, 2);
+        transfer.unacked = 2;
+        assert!(!ack_due(&transfer, &conf, now));
+        transfer.transferred_chunks = vec!(1, 2, 3);
+        transfer.unacked = 3;
+        assert!(ack_due(&transfer, &conf, now));
+
+        // the window stays at the missing chunk, until it was received
+        assert!(acknowledge(&mut transfer, &services, "ignored", "", &conf));
+        assert_eq!(transfer.window_start, 0);
+        transfer.transferred_chunks = vec!(0, 1, 2, 3);
+        transfer.unacked = 1;
+        assert!(ack_due(&transfer, &conf, now));
+
+        assert!(acknowledge(&mut transfer, &services, "ignored", "", &conf));
+        assert_eq!(transfer.window_start, 4);
+        transfer.transferred_chunks = vec!(0, 1, 2, 3, 4, 6);
+        transfer.unacked = 2;
+        assert!(!ack_due(&transfer, &conf, now));
+        transfer.transferred_chunks.push(7);
+        transfer.unacked = 3;
+        assert!(ack_due(&transfer, &conf, now));
+    }
 }
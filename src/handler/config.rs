@@ -0,0 +1,142 @@
+//! Handles "Update Configuration" messages.
+
+use std::sync::Mutex;
+
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use rustc_serialize::hex::FromHex;
+
+#[cfg(not(test))] use rvi::send_message;
+
+use message::{BackendServices, ConfigReport, Notification};
+use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
+
+/// Type for "Update Configuration" messages.
+#[derive(RustcDecodable)]
+pub struct ConfigParams {
+    /// The configuration fragment in `toml` format, with the sections and keys to change.
+    pub config: String,
+    /// Hex encoded HMAC-SHA256 of `config`, keyed with `config_key`.
+    pub signature: String,
+    /// The service, the result of the update is reported to.
+    pub report: String
+}
+
+impl ConfigParams {
+    /// Check the signature of the fragment against the configured `config_key`. Returns a
+    /// `String` with the reason, if the fragment must not be applied.
+    ///
+    /// # Arguments
+    /// * `conf`: The `client` section of the configuration.
+    fn verify(&self, conf: &ClientConfiguration) -> Result<(), String> {
+        let key = try!(conf.config_key.as_ref()
+                       .ok_or("Remote configuration is disabled".to_string()));
+        let signature = try!(self.signature.from_hex()
+                             .map_err(|_| "Signature isn't hex encoded".to_string()));
+
+        let mut hmac = Hmac::new(Sha256::new(), key.as_bytes());
+        hmac.input(self.config.as_bytes());
+        if hmac.result() == MacResult::new(&signature) {
+            Ok(())
+        } else {
+            Err("Invalid signature".to_string())
+        }
+    }
+}
+
+impl HandleMessageParams for ConfigParams {
+    fn handle(&self,
+              _: &Mutex<BackendServices>,
+              _: &Mutex<Transfers>,
+              rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
+        match self.verify(conf) {
+            Ok(..) => true,
+            Err(e) => {
+                error!("Rejecting remote configuration: {}", e);
+                let report = ConfigReport {
+                    status: false,
+                    description: e,
+                    vin: vin.to_string()
+                };
+                try_or!(send_message(rvi_url, report, &self.report), return false);
+                false
+            }
+        }
+    }
+
+    fn get_message(&self) -> Option<Notification> {
+        Some(Notification::Configure(self.config.clone(), self.report.clone()))
+    }
+}
+
+#[cfg(test)]
+fn send_message(url: &str, report: ConfigReport, service: &str) -> Result<bool, bool> {
+    trace!("Would send \"{}\", to {} on {}", report.description, service, url);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::sync::Mutex;
+
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha2::Sha256;
+    use rustc_serialize::hex::ToHex;
+
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
+
+    fn gen_params(config: &str, key: &str) -> ConfigParams {
+        let mut hmac = Hmac::new(Sha256::new(), key.as_bytes());
+        hmac.input(config.as_bytes());
+        ConfigParams {
+            config: config.to_string(),
+            signature: hmac.result().code().to_hex(),
+            report: "/sota/config_report".to_string()
+        }
+    }
+
+    #[test]
+    fn it_accepts_signed_configuration() {
+        test_init!();
+        let services = Mutex::new(get_empty_backend());
+        let transfers = Mutex::new(Transfers::new_test());
+        let mut conf = ClientConfiguration::gen_test();
+        conf.config_key = Some("secret".to_string());
+
+        let params = gen_params("[client]\ntimeout = 60\n", "secret");
+        assert!(params.handle(&services, &transfers, "", "", &conf));
+    }
+
+    #[test]
+    fn it_rejects_invalid_signatures() {
+        test_init!();
+        let services = Mutex::new(get_empty_backend());
+        let transfers = Mutex::new(Transfers::new_test());
+        let mut conf = ClientConfiguration::gen_test();
+        conf.config_key = Some("secret".to_string());
+
+        let params = gen_params("[client]\ntimeout = 60\n", "other");
+        assert!(!params.handle(&services, &transfers, "", "", &conf));
+        let mut params = gen_params("[client]\ntimeout = 60\n", "secret");
+        params.config = "[client]\ntimeout = 1\n".to_string();
+        assert!(!params.handle(&services, &transfers, "", "", &conf));
+    }
+
+    #[test]
+    fn it_rejects_configuration_without_a_key() {
+        test_init!();
+        let services = Mutex::new(get_empty_backend());
+        let transfers = Mutex::new(Transfers::new_test());
+        let conf = ClientConfiguration::gen_test();
+
+        let params = gen_params("[client]\ntimeout = 60\n", "");
+        assert!(!params.handle(&services, &transfers, "", "", &conf));
+    }
+}
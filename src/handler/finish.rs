@@ -5,7 +5,8 @@ use std::sync::Mutex;
 #[cfg(not(test))] use rvi::send_message;
 
 use message::{BackendServices, PackageId, Notification, ServerPackageReport};
-use handler::{Transfers, HandleMessageParams};
+use handler::{Transfers, HandleMessageParams, start_queued};
+use configuration::ClientConfiguration;
 
 /// Type for "Finish Transfer" messages.
 #[derive(RustcDecodable)]
@@ -18,27 +19,37 @@ impl HandleMessageParams for FinishParams {
     fn handle(&self,
               services: &Mutex<BackendServices>,
               transfers: &Mutex<Transfers>,
-              rvi_url: &str, vin: &str, _: &str) -> bool {
+              rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
         let services = services.lock().unwrap();
         let mut transfers = transfers.lock().unwrap();
-        let success = transfers.get(&self.package).map(|t| {
-            t.assemble_package()
-        }).unwrap_or_else(|| {
-            error!("Couldn't find transfer for package {}", self.package);
-            false
-        });
-        if success {
-            transfers.remove(&self.package);
-            info!("Finished transfer of {}", self.package);
-        } else {
-            try_or!(send_message(rvi_url,
-                                 ServerPackageReport {
-                                     package: self.package.clone(),
-                                     status: false,
-                                     description: "checksums didn't match".to_string(),
-                                     vin: vin.to_string()
-                                 }, &services.report), return false);
-        }
+        let assembled = transfers.active.get(&self.package).map(|t| t.assemble_package());
+        let success = match assembled {
+            Some(true) => {
+                transfers.active.remove(&self.package);
+                transfers.announced.remove(&self.package);
+                info!("Finished transfer of {}", self.package);
+                true
+            },
+            None => {
+                error!("Couldn't find transfer for package {}", self.package);
+                false
+            },
+            // A failed transfer can't be finished again, so its slot is released as well
+            Some(false) => {
+                transfers.fail(&self.package, "Package couldn't be verified");
+                try_or!(send_message(rvi_url,
+                                     ServerPackageReport {
+                                         package: self.package.clone(),
+                                         status: false,
+                                         description: "checksums didn't match".to_string(),
+                                         vin: vin.to_string(),
+                                         rollback: None,
+                                         result: None
+                                     }, &services.report), {});
+                false
+            }
+        };
+        start_queued(&services, &mut transfers, rvi_url, vin, conf);
         success
     }
 
@@ -57,8 +68,7 @@ fn send_message(url: &str, chunks: ServerPackageReport, report: &str)
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
-    use std::sync::Mutex;
+    use std::sync::{Arc, Mutex};
 
     use super::*;
     use test_library::*;
@@ -66,12 +76,13 @@ mod test {
     use rustc_serialize::base64;
     use rustc_serialize::base64::ToBase64;
 
-    use handler::{HandleMessageParams, ChunkParams};
+    use handler::{HandleMessageParams, ChunkParams, Transfers};
+    use configuration::ClientConfiguration;
     use message::BackendServices;
-    use persistence::Transfer;
+    use persistence::{Transfer, MemoryStorage};
 
     macro_rules! assert_data_written {
-        ($package:ident, $services:ident, $transfers:ident) => {{
+        ($package:ident, $services:ident, $transfers:ident, $conf:ident) => {{
             let msg = "test\n".to_string();
             let b64_msg = msg.as_bytes().to_base64(
                 base64::Config {
@@ -83,9 +94,10 @@ mod test {
             let chunk = ChunkParams {
                 bytes: b64_msg,
                 index: 1,
-                package: $package.clone()
+                package: $package.clone(),
+                checksum: None
             };
-            assert!(chunk.handle(&$services, &$transfers, "ignored", "", ""));
+            assert!(chunk.handle(&$services, &$transfers, "ignored", "", &$conf));
         }}
     }
 
@@ -93,18 +105,18 @@ mod test {
     fn it_returns_true_on_existing_transfers() {
         test_init!();
         for i in 1..20 {
-            let prefix = PathPrefix::new();
-            let mut transfer = Transfer::new_test(&prefix);
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
             transfer.checksum =
                 "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
             let package = transfer.randomize(i);
-            let transfers = Mutex::new(HashMap::new());
-            transfers.lock().unwrap().insert(package.clone(), transfer);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
-            assert_data_written!(package, services, transfers);
+            assert_data_written!(package, services, transfers, conf);
             let finish = FinishParams { package: package.clone() };
-            assert!(finish.handle(&services, &transfers, "ignored", "", ""));
+            assert!(finish.handle(&services, &transfers, "ignored", "", &conf));
         }
     }
 
@@ -112,19 +124,19 @@ mod test {
     fn it_removes_existing_transfers() {
         test_init!();
         for i in 1..20 {
-            let prefix = PathPrefix::new();
-            let mut transfer = Transfer::new_test(&prefix);
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
             transfer.checksum =
                 "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
             let package = transfer.randomize(i);
-            let transfers = Mutex::new(HashMap::new());
-            transfers.lock().unwrap().insert(package.clone(), transfer);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
-            assert_data_written!(package, services, transfers);
+            assert_data_written!(package, services, transfers, conf);
             let finish = FinishParams { package: package.clone() };
-            assert!(finish.handle(&services, &transfers, "ignored", "", ""));
-            assert!(transfers.lock().unwrap().is_empty());
+            assert!(finish.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.is_empty());
         }
     }
 
@@ -133,11 +145,12 @@ mod test {
         test_init!();
         for i in 1..20 {
             let package = generate_random_package(i);
-            let transfers = Mutex::new(HashMap::new());
+            let transfers = Mutex::new(Transfers::new_test());
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
             let finish = FinishParams { package: package.clone() };
-            assert!(!finish.handle(&services, &transfers, "ignored", "", ""));
+            assert!(!finish.handle(&services, &transfers, "ignored", "", &conf));
         }
     }
 
@@ -145,19 +158,43 @@ mod test {
     fn it_does_not_touch_transfers_on_invalid_transfers() {
         test_init!();
         for i in 1..20 {
-            let prefix = PathPrefix::new();
-            let mut transfer = Transfer::new_test(&prefix);
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
             transfer.checksum =
                 "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
             let package = transfer.randomize(i);
-            let transfers = Mutex::new(HashMap::new());
-            transfers.lock().unwrap().insert(package.clone(), transfer);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
             let services = Mutex::new(BackendServices::new());
+            let conf = ClientConfiguration::gen_test();
 
-            assert_data_written!(package, services, transfers);
+            assert_data_written!(package, services, transfers, conf);
             let finish = FinishParams { package: generate_random_package(i) };
-            assert!(!finish.handle(&services, &transfers, "ignored", "", ""));
-            assert!(!transfers.lock().unwrap().is_empty());
+            assert!(!finish.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(!transfers.lock().unwrap().active.is_empty());
+            assert!(transfers.lock().unwrap().failed.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_releases_the_slot_of_failed_transfers() {
+        test_init!();
+        for i in 1..20 {
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+            transfer.checksum =
+                "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca".to_string();
+            let package = transfer.randomize(i);
+            let transfers = Mutex::new(Transfers::new_test());
+            transfers.lock().unwrap().active.insert(package.clone(), transfer);
+            let services = Mutex::new(BackendServices::new());
+            let mut conf = ClientConfiguration::gen_test();
+            conf.max_transfers = Some(1);
+
+            assert_data_written!(package, services, transfers, conf);
+            let finish = FinishParams { package: package.clone() };
+            assert!(!finish.handle(&services, &transfers, "ignored", "", &conf));
+            let transfers = transfers.lock().unwrap();
+            assert!(transfers.has_free_slot(&conf));
+            assert_eq!(transfers.failed[0].0, package);
         }
     }
 }
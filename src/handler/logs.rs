@@ -0,0 +1,30 @@
+//! Handles "Upload Logs" messages.
+
+use std::sync::Mutex;
+
+use message::{BackendServices, LogServices, Notification};
+use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
+
+/// Type for "Upload Logs" messages.
+#[derive(RustcDecodable)]
+pub struct LogsParams {
+    /// The identifier of the upload, that is sent with every upload message.
+    pub id: String,
+    /// The services, the upload messages are sent to.
+    pub services: LogServices
+}
+
+impl HandleMessageParams for LogsParams {
+    fn handle(&self,
+              _: &Mutex<BackendServices>,
+              _: &Mutex<Transfers>,
+              _: &str, _: &str, _: &ClientConfiguration) -> bool {
+        info!("Server requested the logs with upload id {}", self.id);
+        true
+    }
+
+    fn get_message(&self) -> Option<Notification> {
+        Some(Notification::UploadLogs(self.id.clone(), self.services.clone()))
+    }
+}
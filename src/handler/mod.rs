@@ -14,15 +14,113 @@ mod chunk;
 mod finish;
 mod report;
 mod abort;
+mod uninstall;
+mod config;
+mod logs;
 
-use std::sync::Mutex;
-use std::collections::HashMap;
-use message::{BackendServices, PackageId, Notification};
-use persistence::Transfer;
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use message::{BackendServices, PackageId, Notification, UserPackage};
+use configuration::ClientConfiguration;
+use persistence::{Transfer, Storage};
+#[cfg(test)] use persistence::MemoryStorage;
 
-/// Type alias to hide the internal `HashMap`, that is used to store
-/// [`Transfer`](../persistence/struct.Transfer.html)s.
-pub type Transfers = HashMap<PackageId, Transfer>;
+/// Type to hold the bookkeeping for [`Transfer`](../persistence/struct.Transfer.html)s.
+pub struct Transfers {
+    /// The currently in-progress `Transfer`s.
+    pub active: HashMap<PackageId, Transfer>,
+    /// The packages the server announced as available, with their sizes.
+    pub announced: HashMap<PackageId, UserPackage>,
+    /// Transfers, that are waiting for a free slot, in the order they will be started.
+    pub queued: VecDeque<StartParams>,
+    /// The [`Storage`](../persistence/trait.Storage.html) new `Transfer`s are kept in.
+    pub storage: Arc<Storage>,
+    /// Transfers, that failed while handling the current message, with the reason. They are
+    /// passed on to the `main_loop` by the [`ServiceHandler`](struct.ServiceHandler.html).
+    pub failed: Vec<(PackageId, String)>
+}
+
+impl Transfers {
+    /// Create a new, empty `Transfers` object.
+    ///
+    /// # Arguments
+    /// * `storage`: The storage for chunks and packages of new `Transfer`s.
+    pub fn new(storage: Arc<Storage>) -> Transfers {
+        Transfers {
+            active: HashMap::new(),
+            announced: HashMap::new(),
+            queued: VecDeque::new(),
+            storage: storage,
+            failed: Vec::new()
+        }
+    }
+
+    /// Create a new `Transfers` object and restore the `Transfer`s persisted in `storage`, so
+    /// they can be resumed, when the server restarts them. Downloads without valid metadata
+    /// aren't restored.
+    ///
+    /// # Arguments
+    /// * `storage`: The storage for chunks and packages of `Transfer`s.
+    pub fn restore(storage: Arc<Storage>) -> Transfers {
+        let mut transfers = Transfers::new(storage.clone());
+        for package in try_or!(storage.downloads(), return transfers) {
+            match Transfer::restore(storage.clone(), &package) {
+                Ok(transfer) => {
+                    info!("Restored transfer for package {} with {} chunks",
+                          package, transfer.transferred_chunks.len());
+                    let _ = transfers.active.insert(package, transfer);
+                },
+                Err(e) => warn!("Couldn't restore transfer for package {}: {}", package, e)
+            }
+        }
+        transfers
+    }
+
+    /// Check whether another `Transfer` may be started, without exceeding `max_transfers`.
+    ///
+    /// # Arguments
+    /// * `conf`: The `client` section of the configuration.
+    pub fn has_free_slot(&self, conf: &ClientConfiguration) -> bool {
+        conf.max_transfers.map(|max| (self.active.len() as i64) < max as i64).unwrap_or(true)
+    }
+
+    /// Drop the `Transfer` of `package`, as it failed for `reason`, and record the failure for
+    /// the `main_loop`.
+    ///
+    /// # Arguments
+    /// * `package`: The package of the failed `Transfer`.
+    /// * `reason`: A short description of the failure.
+    pub fn fail(&mut self, package: &PackageId, reason: &str) {
+        let _ = self.active.remove(package);
+        self.failed.push((package.clone(), reason.to_string()));
+    }
+
+    /// Remove all `Transfer`s, that didn't receive a chunk for more than `timeout` seconds.
+    /// Returns the packages of the removed `Transfer`s.
+    ///
+    /// # Arguments
+    /// * `timeout`: The timeout in seconds.
+    /// * `now`: The current time as a unix epoch timestamp.
+    pub fn expire(&mut self, timeout: i64, now: i64) -> Vec<PackageId> {
+        let timed_out: Vec<PackageId> = self.active.iter()
+            .filter(|&(_, t)| now - t.last_chunk_received > timeout)
+            .map(|(p, _)| p.clone())
+            .collect();
+
+        for package in timed_out.iter() {
+            info!("Transfer for package {} timed out after {} s", package, timeout);
+            let _ = self.active.remove(package);
+        }
+        timed_out
+    }
+
+    /// Create a new, empty `Transfers` object, that keeps its `Transfer`s in memory. To be used in
+    /// tests.
+    #[cfg(test)]
+    pub fn new_test() -> Transfers {
+        Transfers::new(Arc::new(MemoryStorage::new()))
+    }
+}
 
 /// Trait that every message handler needs to implement.
 pub trait HandleMessageParams {
@@ -30,7 +128,7 @@ pub trait HandleMessageParams {
     fn handle(&self,
               services: &Mutex<BackendServices>,
               transfers: &Mutex<Transfers>,
-              rvi_url: &str, vin: &str, storage_dir: &str)
+              rvi_url: &str, vin: &str, conf: &ClientConfiguration)
         -> bool;
 
     /// Return a [`Notification`](../message/enum.Notification.html) to be passed to the
@@ -41,8 +139,52 @@ pub trait HandleMessageParams {
 pub use self::service::ServiceHandler;
 
 pub use self::notify::NotifyParams;
-pub use self::start::StartParams;
-pub use self::chunk::ChunkParams;
+pub use self::start::{StartParams, start_queued};
+pub use self::chunk::{ChunkParams, ack_due, acknowledge};
 pub use self::finish::FinishParams;
 pub use self::report::ReportParams;
 pub use self::abort::AbortParams;
+pub use self::uninstall::UninstallParams;
+pub use self::config::ConfigParams;
+pub use self::logs::LogsParams;
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use test_library::*;
+    use persistence::{Transfer, MemoryStorage, Storage};
+
+    #[test]
+    fn it_expires_timed_out_transfers() {
+        test_init!();
+        let mut transfers = Transfers::new_test();
+        let mut stale = Transfer::new_test(Arc::new(MemoryStorage::new()));
+        let stale_package = stale.randomize(10);
+        let now = stale.last_chunk_received;
+        stale.last_chunk_received = now - 21;
+        let mut fresh = Transfer::new_test(Arc::new(MemoryStorage::new()));
+        let fresh_package = fresh.randomize(11);
+        transfers.active.insert(stale_package.clone(), stale);
+        transfers.active.insert(fresh_package.clone(), fresh);
+
+        assert_eq!(transfers.expire(20, now), vec!(stale_package));
+        assert!(transfers.active.contains_key(&fresh_package));
+        assert_eq!(transfers.active.len(), 1);
+    }
+
+    #[test]
+    fn it_restores_persisted_transfers() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        transfer.persist().unwrap();
+        storage.write_chunk(&generate_random_package(11), 0, b"orphan").unwrap();
+
+        let transfers = Transfers::restore(storage.clone());
+        assert_eq!(transfers.active.len(), 1);
+        assert!(transfers.active.contains_key(&package));
+    }
+}
@@ -5,6 +5,7 @@ use std::sync::Mutex;
 use message::{BackendServices, UserMessage, UserPackage};
 use message::Notification;
 use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
 
 impl fmt::Display for UserPackage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -24,13 +25,15 @@ pub struct NotifyParams {
 impl HandleMessageParams for NotifyParams {
     fn handle(&self,
               services: &Mutex<BackendServices>,
-              _: &Mutex<Transfers>,
-              _: &str, _: &str, _: &str) -> bool {
+              transfers: &Mutex<Transfers>,
+              _: &str, _: &str, _: &ClientConfiguration) -> bool {
         let mut services = services.lock().unwrap();
+        let mut transfers = transfers.lock().unwrap();
         services.update(&self.services);
 
         for package in &self.packages {
             info!("New package available: {}", package);
+            transfers.announced.insert(package.package.clone(), package.clone());
         }
 
         true
@@ -49,12 +52,11 @@ mod test {
     use super::*;
     use test_library::get_empty_backend;
 
-    use std::collections::HashMap;
     use std::sync::Mutex;
 
     use message::{BackendServices, PackageId, UserPackage, Notification};
-    use handler::HandleMessageParams;
-    use persistence::Transfer;
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
 
     use rand;
     use rand::Rng;
@@ -72,7 +74,12 @@ mod test {
 
             let notify_package = UserPackage {
                 package: package,
-                size: j as u64
+                size: j as u64,
+                priority: None,
+                mandatory: None,
+                deadline: None,
+                description: None,
+                requires: None
             };
 
             packages.push(notify_package);
@@ -115,14 +122,18 @@ mod test {
                 start: start.clone(),
                 ack: ack.clone(),
                 report: report.clone(),
-                packages: packages.clone()
+                packages: packages.clone(),
+                full_package: None,
+                deferred: None,
+                install_deferred: None
             };
             let notify = NotifyParams {
                 packages: gen_packages(i),
                 services: services_new
             };
-            let transfers = Mutex::new(HashMap::<PackageId, Transfer>::new());
-            assert!(notify.handle(&services_old, &transfers, "", "", ""));
+            let transfers = Mutex::new(Transfers::new_test());
+            let conf = ClientConfiguration::gen_test();
+            assert!(notify.handle(&services_old, &transfers, "", "", &conf));
             let services = services_old.lock().unwrap();
             assert_eq!(services.start, start);
             assert_eq!(services.ack, ack);
@@ -159,14 +170,18 @@ mod test {
                 start: start.clone(),
                 ack: ack.clone(),
                 report: report.clone(),
-                packages: packages.clone()
+                packages: packages.clone(),
+                full_package: None,
+                deferred: None,
+                install_deferred: None
             };
             let notify = NotifyParams {
                 packages: gen_packages(i),
                 services: services_new
             };
-            let transfers = Mutex::new(HashMap::<PackageId, Transfer>::new());
-            assert!(notify.handle(&services_old, &transfers, "", "", ""));
+            let transfers = Mutex::new(Transfers::new_test());
+            let conf = ClientConfiguration::gen_test();
+            assert!(notify.handle(&services_old, &transfers, "", "", &conf));
             match notify.get_message().unwrap() {
                 Notification::Notify(m) => {
                     assert_eq!(m.services.start, start);
@@ -179,6 +194,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_remembers_announced_packages() {
+        test_init!();
+        for i in 1..20 {
+            let packages = gen_packages(i);
+            let services = Mutex::new(get_empty_backend());
+            let notify = NotifyParams {
+                packages: packages.clone(),
+                services: get_empty_backend()
+            };
+            let transfers = Mutex::new(Transfers::new_test());
+            let conf = ClientConfiguration::gen_test();
+            assert!(notify.handle(&services, &transfers, "", "", &conf));
+
+            let transfers = transfers.lock().unwrap();
+            for package in packages {
+                assert_eq!(transfers.announced.get(&package.package), Some(&package));
+            }
+        }
+    }
+
     #[test]
     fn it_promotes_packages() {
         test_init!();
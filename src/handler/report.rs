@@ -4,6 +4,7 @@ use std::sync::Mutex;
 
 use message::{BackendServices, Notification};
 use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
 
 #[derive(RustcDecodable)]
 /// Type for "Get All Packages" messages.
@@ -13,7 +14,7 @@ impl HandleMessageParams for ReportParams {
     fn handle(&self,
               _: &Mutex<BackendServices>,
               _: &Mutex<Transfers>,
-              _: &str, _: &str, _: &str) -> bool {
+              _: &str, _: &str, _: &ClientConfiguration) -> bool {
         true
     }
 
@@ -7,9 +7,9 @@ use jsonrpc;
 use jsonrpc::{OkResponse, ErrResponse};
 
 use std::io::{Read, Write};
-use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Sender;
+use std::thread;
 use std::thread::sleep_ms;
 
 use time;
@@ -17,31 +17,38 @@ use hyper::server::{Handler, Request, Response};
 use rustc_serialize::{json, Decodable};
 use rustc_serialize::json::Json;
 
-use rvi::{Message, RVIHandler, Service};
+use rvi::{Connectivity, Message, RVIHandler, Service, send_message};
 
-use message::{BackendServices, LocalServices, Notification};
+use message::{BackendServices, LocalServices, Notification, PackageId, ServerPackageReport};
 use handler::{NotifyParams, StartParams, ChunkParams, FinishParams};
-use handler::{ReportParams, AbortParams, HandleMessageParams, Transfers};
+use handler::{ReportParams, AbortParams, UninstallParams, HandleMessageParams, Transfers};
+use handler::{ConfigParams, LogsParams, ack_due, acknowledge, start_queued};
 use configuration::Configuration;
 
 /// Type that encodes a single service handler.
 ///
 /// Holds the necessary state, like in-progress transfers, that are needed for handling incoming
 /// messages and sending replies to RVI. Needs to be thread safe as
-/// [`hyper`](../../../hyper/index.html) handles requests asynchronously.
+/// [`hyper`](../../../hyper/index.html) handles requests asynchronously. Whoever needs both, has to
+/// lock `services` before `transfers`, or the handlers and the timer deadlock each other.
 pub struct ServiceHandler {
     /// The full URL, where RVI can be reached.
     rvi_url: String,
     /// A `Sender` that connects the handlers with the `main_loop`.
     sender: Mutex<Sender<Notification>>,
     /// The service URLs that the SOTA server advertised.
-    services: Mutex<BackendServices>,
+    services: Arc<Mutex<BackendServices>>,
     /// The currently in-progress `Transfer`s.
     transfers: Arc<Mutex<Transfers>>,
-    /// The full `Configuration` of sota_client.
-    conf: Configuration,
+    /// The full `Configuration` of sota_client, shared with the `main_loop`, that applies remote
+    /// configuration.
+    conf: Arc<Mutex<Configuration>>,
+    /// The remote services, RVI announced as available.
+    connectivity: Connectivity,
     /// The VIN of this device, as returned by RVI.
-    vin: String
+    vin: String,
+    /// Whether the timer for the in-progress `Transfer`s was already started.
+    timer_started: bool
 }
 
 impl ServiceHandler {
@@ -51,52 +58,86 @@ impl ServiceHandler {
     /// * `transfers`: A `Transfers` object to store the in-progress `Transfer`s.
     /// * `sender`: A `Sender` to call back into the `main_loop`.
     /// * `url`: The full URL, where RVI can be reached.
-    /// * `c`: The full `Configuration` of sota_client.
+    /// * `c`: The full `Configuration` of sota_client, shared with the `main_loop`.
+    /// * `connectivity`: Records the remote services, RVI announces as available.
     pub fn new(transfers: Arc<Mutex<Transfers>>,
                sender: Sender<Notification>,
-               url: String, c: Configuration) -> ServiceHandler {
+               url: String, c: Arc<Mutex<Configuration>>,
+               connectivity: Connectivity) -> ServiceHandler {
         let services = BackendServices {
             start: String::new(),
             ack: String::new(),
             report: String::new(),
-            packages: String::new()
+            packages: String::new(),
+            full_package: None,
+            deferred: None,
+            install_deferred: None
         };
 
         ServiceHandler {
             rvi_url: url,
             sender: Mutex::new(sender),
-            services: Mutex::new(services),
+            services: Arc::new(Mutex::new(services)),
             transfers: transfers,
+            connectivity: connectivity,
             vin: String::new(),
-            conf: c
+            conf: c,
+            timer_started: false
         }
     }
 
-    /// Starts a infinite loop to expire timed out transfers. Checks once a second for timed out
-    /// transfers.
+    /// Starts a infinite loop to expire timed out transfers, to start queued transfers once a slot
+    /// is free and to acknowledge chunks, that were held back for longer than `ack_interval`.
+    /// Checks once a second. Expired transfers are
+    /// reported as failed to the server and to the `main_loop`.
     ///
     /// # Arguments
-    /// * `transfers`: Pointer to a `Transfers` object, that stores the transfers to be checked for
-    ///   expired timeouts.
-    /// * `timeout`: The timeout in seconds.
+    /// * `transfers`: Pointer to a `Transfers` object, that stores the transfers to be checked.
+    /// * `services`: The service URLs of the server, acknowledgements and reports are sent to.
+    /// * `sender`: A `Sender` to notify the `main_loop` of expired transfers.
+    /// * `rvi_url`: The URL, where RVI can be found.
+    /// * `vin`: The VIN of this device.
+    /// * `conf`: The shared configuration. Its `client` section holds the timeout in seconds and
+    ///   the acknowledgement settings, and is read again on every check.
     pub fn start_timer(transfers: &Mutex<Transfers>,
-                       timeout: i64) {
+                       services: &Mutex<BackendServices>,
+                       sender: &Sender<Notification>,
+                       rvi_url: &str, vin: &str,
+                       conf: &Mutex<Configuration>) {
         loop {
             sleep_ms(1000);
             let time_now = time::get_time().sec;
+            let client = conf.lock().unwrap().client.clone();
+            // Lock order throughout the crate: `services` before `transfers`
+            let services = services.lock().unwrap();
             let mut transfers = transfers.lock().unwrap();
 
-            let mut timed_out = Vec::new();
-            for transfer in transfers.deref_mut() {
-                if time_now - transfer.1.last_chunk_received > timeout {
-                    timed_out.push(transfer.0.clone());
+            if let Some(timeout) = client.timeout {
+                for package in transfers.expire(timeout, time_now) {
+                    let report = ServerPackageReport {
+                        package: package.clone(),
+                        status: false,
+                        description: format!("Transfer timed out after {} seconds", timeout),
+                        vin: vin.to_string(),
+                        rollback: None,
+                        result: None
+                    };
+                    if let Err(e) = send_message(rvi_url, report, &services.report) {
+                        error!("Couldn't report timed out transfer of {}: {}", package, e);
+                    }
+                    let reason = "Transfer timed out".to_string();
+                    if let Err(e) = sender.send(Notification::DownloadFailed(package, reason)) {
+                        error!("{}", e);
+                    }
                 }
             }
+            // Slots are also released by finished, failed and aborted transfers
+            start_queued(&services, &mut transfers, rvi_url, vin, &client);
 
-            for transfer in timed_out {
-                info!("Transfer for package {} timed out after {} ms",
-                      transfer, timeout);
-                let _ = transfers.remove(&transfer);
+            for transfer in transfers.active.values_mut() {
+                if ack_due(transfer, &client, time_now) {
+                    let _ = acknowledge(transfer, &services, rvi_url, vin, &client);
+                }
             }
         }
     }
@@ -110,8 +151,8 @@ impl ServiceHandler {
     }
 
     /// Create a message handler `D`, and let it process the `message`. If it returns a
-    /// Notification, forward it to the `main_loop`. Returns a `jsonrpc` response indicating
-    /// success or failure.
+    /// Notification, forward it to the `main_loop`, as well as the transfers, that failed while
+    /// handling the message. Returns a `jsonrpc` response indicating success or failure.
     ///
     /// # Arguments
     /// * `message`: The message, that should be handled.
@@ -120,11 +161,18 @@ impl ServiceHandler {
         where D: Decodable + HandleMessageParams {
         json::decode::<jsonrpc::Request<Message<D>>>(&message).map(|p| {
             let handler = &p.params.parameters[0];
+            let conf = self.conf.lock().unwrap().client.clone();
             let result = handler.handle(&self.services,
                                         &self.transfers,
                                         &self.rvi_url,
                                         &self.vin,
-                                        &self.conf.client.storage_dir);
+                                        &conf);
+            let failed: Vec<(PackageId, String)> =
+                self.transfers.lock().unwrap().failed.drain(..).collect();
+            for (package, reason) in failed {
+                self.push_notify(Notification::DownloadFailed(package, reason));
+            }
+
             if result {
                 handler.get_message().map(|m| { self.push_notify(m); });
                 Ok(OkResponse::new(p.id, None))
@@ -134,6 +182,24 @@ impl ServiceHandler {
         }).ok()
     }
 
+    /// Extract the services of other RVI nodes from the parameters of a `services_available` or
+    /// `services_unavailable` callback. The services of this device are skipped.
+    ///
+    /// # Arguments
+    /// * `params`: The parameters of the callback.
+    fn remote_services(&self, params: Option<&Json>) -> Vec<String> {
+        let local = format!("/{}/", self.vin);
+        params.and_then(|x| x.as_object())
+            .and_then(|x| x.get("services"))
+            .and_then(|x| x.as_array())
+            .map(|services| services.iter()
+                 .filter_map(|s| s.as_string())
+                 .filter(|s| !s.contains(&local[..]))
+                 .map(|s| s.to_string())
+                 .collect())
+            .unwrap_or(Vec::new())
+    }
+
     /// Try to parse the type of a message and forward it to the appropriate message handler.
     /// Returns the result of the message handling or a `jsonrpc` result indicating a parser error.
     ///
@@ -166,7 +232,13 @@ impl ServiceHandler {
         let method = try!(obj.get("method").and_then(|x| x.as_string())
                           .ok_or(ErrResponse::invalid_request(rpc_id)));
 
-        if method == "services_available" {
+        if method == "services_available" || method == "services_unavailable" {
+            let services = self.remote_services(obj.get("params"));
+            if method == "services_available" {
+                self.connectivity.available(&services);
+            } else {
+                self.connectivity.unavailable(&services);
+            }
             Ok(OkResponse::new(rpc_id, None))
         }
         else if method != "message" {
@@ -184,7 +256,10 @@ impl ServiceHandler {
                            ChunkParams,  "/sota/chunk",
                            FinishParams, "/sota/finish",
                            ReportParams, "/sota/getpackages",
-                           AbortParams,  "/sota/abort");
+                           AbortParams,  "/sota/abort",
+                           UninstallParams, "/sota/uninstall",
+                           ConfigParams, "/sota/config",
+                           LogsParams,   "/sota/logs");
 
             Err(ErrResponse::invalid_request(rpc_id))
         }
@@ -222,6 +297,25 @@ impl Handler for ServiceHandler {
 impl RVIHandler for ServiceHandler {
     fn register(&mut self, services: Vec<Service>) {
         self.vin = LocalServices::new(&services)
-            .get_vin(self.conf.client.vin_match);
+            .get_vin(self.conf.lock().unwrap().client.vin_match);
+
+        if self.timer_started {
+            return;
+        }
+        self.timer_started = true;
+
+        if self.conf.lock().unwrap().client.timeout.is_none() {
+            info!("No timeout configured, transfers will never time out.");
+        }
+
+        let transfers = self.transfers.clone();
+        let services = self.services.clone();
+        let sender = self.sender.lock().unwrap().clone();
+        let rvi_url = self.rvi_url.clone();
+        let vin = self.vin.clone();
+        let conf = self.conf.clone();
+        let _ = thread::spawn(move || {
+            ServiceHandler::start_timer(&transfers, &services, &sender, &rvi_url, &vin, &conf);
+        });
     }
 }
@@ -1,14 +1,25 @@
 //! Handles "Start Transfer" messages.
 
+use std::cmp::Ordering;
 use std::sync::Mutex;
 
-use message::{BackendServices, PackageId, ChunkReceived, Notification};
-use handler::{HandleMessageParams, Transfers};
-use persistence::Transfer;
-use rvi::send_message;
+use time;
+
+#[cfg(not(test))] use rvi::send_message;
+#[cfg(test)] use rustc_serialize::Encodable;
+
+use message::{BackendServices, PackageId, Notification, ServerPackageReport};
+use message::{FullPackageRequired, TransferDeferred};
+use handler::{HandleMessageParams, Transfers, acknowledge};
+use configuration::ClientConfiguration;
+use persistence::{Transfer, Encoding, check_space};
+
+/// Seconds the server is asked to wait before retrying a deferred transfer, if no
+/// `transfer_retry` is configured.
+const DEFAULT_RETRY_AFTER: i64 = 60;
 
 /// Type for "Start Transfer" messages.
-#[derive(RustcDecodable)]
+#[derive(RustcDecodable, Clone)]
 pub struct StartParams {
     /// The amount of chunks this `Transfer` will have.
     pub chunkscount: u64,
@@ -16,33 +27,530 @@ pub struct StartParams {
     pub checksum: String,
     /// The `PackageId` of this `Transfer`.
     pub package: PackageId,
+    /// The installed package, the chunks are a [`delta`](../delta/index.html) against. Chunks
+    /// hold the full package, if not set.
+    ///
+    /// Deltas are only applied against packages in the cache of the client, not against the
+    /// installed files. A base, that is installed but was already evicted from the cache, always
+    /// falls back to a full transfer.
+    pub delta_base: Option<PackageId>,
+    /// The [`Encoding`](../persistence/enum.Encoding.html) of the chunks, e.g. `gzip`. Chunks
+    /// hold raw data, if not set.
+    pub encoding: Option<String>
+}
+
+impl StartParams {
+    /// Parse the `Encoding` of this transfer. Returns a `String` with a error message for
+    /// unsupported encodings.
+    fn get_encoding(&self) -> Result<Encoding, String> {
+        self.encoding.as_ref().map(|e| e.parse()).unwrap_or(Ok(Encoding::Identity))
+    }
+
+    /// Check whether this message restarts the in-progress `transfer`, i.e. announces the same
+    /// package data.
+    ///
+    /// # Arguments
+    /// * `transfer`: The in-progress `Transfer` of the same package.
+    fn resumes(&self, transfer: &Transfer) -> bool {
+        transfer.checksum == self.checksum &&
+            transfer.base == self.delta_base &&
+            self.get_encoding().ok() == Some(transfer.encoding)
+    }
+
+    /// Start this transfer, if it passes the admission checks, and acknowledge it to the server.
+    /// Returns `false`, if the transfer was rejected.
+    ///
+    /// # Arguments
+    /// * `services`: The service URLs of the server.
+    /// * `transfers`: The `Transfers` to add the new `Transfer` to.
+    /// * `rvi_url`: The URL, where RVI can be found.
+    /// * `vin`: The VIN of this device.
+    /// * `conf`: The `client` section of the configuration.
+    fn start(&self, services: &BackendServices, transfers: &mut Transfers,
+             rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
+        info!("Starting transfer for package {}", self.package);
+
+        let admission = match transfers.announced.get(&self.package) {
+            Some(announced) => check_space(&*transfers.storage, announced.size,
+                                           conf.storage_quota),
+            None => {
+                warn!("No size announced for package {}, skipping space check",
+                      self.package);
+                Ok(())
+            }
+        }.and_then(|_| self.get_encoding());
+
+        let encoding = match admission {
+            Ok(encoding) => encoding,
+            Err(e) => {
+                error!("Rejecting transfer for package {}: {}", self.package, e);
+                try_or!(send_message(rvi_url,
+                                     ServerPackageReport {
+                                         package: self.package.clone(),
+                                         status: false,
+                                         description: e,
+                                         vin: vin.to_string(),
+                                         rollback: None,
+                                         result: None
+                                     }, &services.report), return false);
+                return false;
+            }
+        };
+
+        if let Some(ref base) = self.delta_base {
+            if !transfers.storage.has_package(base) {
+                return self.reject_delta(base, services, rvi_url, vin);
+            }
+        }
+
+        // Drop a restarted transfer first, as it removes its chunks and metadata
+        let _ = transfers.active.remove(&self.package);
+        let mut transfer = Transfer::new(transfers.storage.clone(),
+                                         self.package.clone(),
+                                         self.checksum.clone());
+        transfer.base = self.delta_base.clone();
+        transfer.encoding = encoding;
+        transfer.chunkscount = self.chunkscount;
+        try_or!(transfer.persist(), {});
+
+        if !acknowledge(&mut transfer, services, rvi_url, vin, conf) {
+            error!("Dropping transfer for package {}, as it couldn't be acknowledged",
+                   self.package);
+            return false;
+        }
+        let _ = transfers.active.insert(self.package.clone(), transfer);
+        true
+    }
+
+    /// Reject this delta transfer, as its `base` isn't cached. Asks the server for the full
+    /// package, if it provides the "Full Package Required" service, and reports a failed
+    /// installation otherwise. Always returns `false`.
+    ///
+    /// # Arguments
+    /// * `base`: The unavailable base of the delta.
+    /// * `services`: The service URLs of the server.
+    /// * `rvi_url`: The URL, where RVI can be found.
+    /// * `vin`: The VIN of this device.
+    fn reject_delta(&self, base: &PackageId, services: &BackendServices,
+                    rvi_url: &str, vin: &str) -> bool {
+        match services.full_package {
+            Some(ref full_package) => {
+                warn!("Base {} for delta of package {} isn't available, requesting full package",
+                      base, self.package);
+                try_or!(send_message(rvi_url,
+                                     FullPackageRequired {
+                                         package: self.package.clone(),
+                                         base: base.clone(),
+                                         vin: vin.to_string()
+                                     }, full_package), return false);
+            },
+            None => {
+                error!("Rejecting delta for package {}, as base {} isn't available",
+                       self.package, base);
+                try_or!(send_message(rvi_url,
+                                     ServerPackageReport {
+                                         package: self.package.clone(),
+                                         status: false,
+                                         description: format!("Base {} of the delta isn't \
+                                                               available", base),
+                                         vin: vin.to_string(),
+                                         rollback: None,
+                                         result: None
+                                     }, &services.report), return false);
+            }
+        }
+        false
+    }
+
+    /// Tell the server to retry this transfer after `transfer_retry` seconds, as all slots and the
+    /// queue are occupied. Reports a failed installation instead, if the server doesn't provide
+    /// the "Transfer Deferred" service. Always returns `false`.
+    ///
+    /// # Arguments
+    /// * `services`: The service URLs of the server.
+    /// * `rvi_url`: The URL, where RVI can be found.
+    /// * `vin`: The VIN of this device.
+    /// * `conf`: The `client` section of the configuration.
+    fn defer(&self, services: &BackendServices, rvi_url: &str, vin: &str,
+             conf: &ClientConfiguration) -> bool {
+        let retry_after = conf.transfer_retry.unwrap_or(DEFAULT_RETRY_AFTER);
+        warn!("Deferring transfer for package {}, all slots are occupied", self.package);
+
+        match services.deferred {
+            Some(ref deferred) => {
+                try_or!(send_message(rvi_url,
+                                     TransferDeferred {
+                                         package: self.package.clone(),
+                                         retry_after: retry_after,
+                                         queued: None,
+                                         vin: vin.to_string()
+                                     }, deferred), return false);
+            },
+            None => {
+                try_or!(send_message(rvi_url,
+                                     ServerPackageReport {
+                                         package: self.package.clone(),
+                                         status: false,
+                                         description: format!("All transfer slots are \
+                                                               occupied, retry after {} \
+                                                               seconds", retry_after),
+                                         vin: vin.to_string(),
+                                         rollback: None,
+                                         result: None
+                                     }, &services.report), return false);
+            }
+        }
+        false
+    }
+
+    /// Queue this transfer until a slot frees up. Transfers are queued by the urgency of their
+    /// announced updates. Tells the server the position in the queue through the "Transfer
+    /// Deferred" service, so it can tell a queued transfer from a lost one. Without the service,
+    /// the server only learns about the transfer once it starts. Tells the server to retry later
+    /// and returns `false`, if the queue is full.
+    ///
+    /// # Arguments
+    /// * `services`: The service URLs of the server.
+    /// * `transfers`: The `Transfers` to queue this transfer in.
+    /// * `rvi_url`: The URL, where RVI can be found.
+    /// * `vin`: The VIN of this device.
+    /// * `conf`: The `client` section of the configuration.
+    fn enqueue(&self, services: &BackendServices, transfers: &mut Transfers,
+               rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
+        transfers.queued.retain(|s| s.package != self.package);
+
+        let queue_full = conf.max_queued
+            .map(|max| transfers.queued.len() as i64 >= max as i64)
+            .unwrap_or(false);
+        if queue_full {
+            return self.defer(services, rvi_url, vin, conf);
+        }
+
+        let position = {
+            let announced = &transfers.announced;
+            let update = announced.get(&self.package);
+            transfers.queued.iter().position(|queued| {
+                match (update, announced.get(&queued.package)) {
+                    (Some(update), Some(other)) => update.cmp_urgency(other) == Ordering::Less,
+                    (Some(_), None) => true,
+                    _ => false
+                }
+            }).unwrap_or(transfers.queued.len())
+        };
+
+        info!("Queueing transfer for package {} at position {}", self.package, position);
+        transfers.queued.insert(position, self.clone());
+
+        if let Some(ref deferred) = services.deferred {
+            try_or!(send_message(rvi_url,
+                                 TransferDeferred {
+                                     package: self.package.clone(),
+                                     retry_after: conf.transfer_retry
+                                         .unwrap_or(DEFAULT_RETRY_AFTER),
+                                     queued: Some(position as u64),
+                                     vin: vin.to_string()
+                                 }, deferred), {});
+        }
+        true
+    }
 }
 
 impl HandleMessageParams for StartParams {
     fn handle(&self,
               services: &Mutex<BackendServices>,
               transfers: &Mutex<Transfers>,
-              rvi_url: &str, vin: &str, storage_dir: &str) -> bool {
+              rvi_url: &str, vin: &str, conf: &ClientConfiguration) -> bool {
         let services = services.lock().unwrap();
         let mut transfers = transfers.lock().unwrap();
 
-        info!("Starting transfer for package {}", self.package);
+        let restarting = match transfers.active.get_mut(&self.package) {
+            Some(transfer) => {
+                if self.resumes(transfer) {
+                    info!("Resuming transfer for package {} with {} chunks",
+                          self.package, transfer.transferred_chunks.len());
+                    transfer.last_chunk_received = time::get_time().sec;
+                    return acknowledge(transfer, &services, rvi_url, vin, conf);
+                }
+                info!("Restarting transfer for package {}", self.package);
+                true
+            },
+            None => false
+        };
+
+        if !restarting && !transfers.has_free_slot(conf) {
+            return self.enqueue(&services, &mut transfers, rvi_url, vin, conf);
+        }
 
-        let transfer = Transfer::new(storage_dir.to_string(),
-                                     self.package.clone(),
-                                     self.checksum.clone());
+        self.start(&services, &mut transfers, rvi_url, vin, conf)
+    }
+
+    fn get_message(&self) -> Option<Notification> { None }
+}
 
-        let chunk_received = ChunkReceived {
-            package: self.package.clone(),
-            chunks: transfer.transferred_chunks.clone(),
-            vin: vin.to_string()
+/// Start queued transfers, as long as there are free slots.
+///
+/// # Arguments
+/// * `services`: The service URLs of the server.
+/// * `transfers`: The `Transfers` holding the queue.
+/// * `rvi_url`: The URL, where RVI can be found.
+/// * `vin`: The VIN of this device.
+/// * `conf`: The `client` section of the configuration.
+pub fn start_queued(services: &BackendServices, transfers: &mut Transfers,
+                    rvi_url: &str, vin: &str, conf: &ClientConfiguration) {
+    while transfers.has_free_slot(conf) {
+        let start = match transfers.queued.pop_front() {
+            Some(start) => start,
+            None => break
         };
+        info!("Starting queued transfer for package {}", start.package);
+        let _ = start.start(services, transfers, rvi_url, vin, conf);
+    }
+}
 
-        let _ = transfers.insert(self.package.clone(), transfer);
+#[cfg(test)]
+fn send_message<E: Encodable>(url: &str, _: E, addr: &str)
+    -> Result<bool, bool> {
+    trace!("Would send message to {} on {}", addr, url);
+    Ok(true)
+}
 
-        try_or!(send_message(rvi_url, chunk_received, &services.ack), return false);
-        true
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use test_library::*;
+
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
+    use message::{BackendServices, UserPackage};
+    use persistence::{Storage, Encoding};
+
+    fn gen_start(i: usize, size: u64, quota: Option<i64>)
+        -> (StartParams, Mutex<Transfers>, ClientConfiguration) {
+        let package = generate_random_package(i);
+        let transfers = Mutex::new(Transfers::new_test());
+        transfers.lock().unwrap().announced.insert(package.clone(), UserPackage {
+            package: package.clone(),
+            size: size,
+            priority: None,
+            mandatory: None,
+            deadline: None,
+            description: None,
+            requires: None
+        });
+
+        let mut conf = ClientConfiguration::gen_test();
+        conf.storage_quota = quota;
+
+        let start = StartParams {
+            chunkscount: 1,
+            checksum: "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string(),
+            package: package,
+            delta_base: None,
+            encoding: None
+        };
+        (start, transfers, conf)
     }
 
-    fn get_message(&self) -> Option<Notification> { None }
+    #[test]
+    fn it_starts_transfers_that_fit_into_the_storage() {
+        test_init!();
+        for i in 1..20 {
+            let (start, transfers, conf) = gen_start(i, 512, Some(1024));
+            let services = Mutex::new(BackendServices::new());
+
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.contains_key(&start.package));
+        }
+    }
+
+    #[test]
+    fn it_rejects_transfers_exceeding_the_quota() {
+        test_init!();
+        for i in 1..20 {
+            let (start, transfers, conf) = gen_start(i, 513, Some(1024));
+            let services = Mutex::new(BackendServices::new());
+
+            assert!(!start.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_starts_deltas_against_available_packages() {
+        test_init!();
+        for i in 1..20 {
+            let (mut start, transfers, conf) = gen_start(i, 512, None);
+            let services = Mutex::new(BackendServices::new());
+            let base = generate_random_package(i);
+            {
+                let transfers = transfers.lock().unwrap();
+                transfers.storage.stage_package(&base).unwrap().commit().unwrap();
+            }
+            start.delta_base = Some(base.clone());
+
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            let transfers = transfers.lock().unwrap();
+            assert_eq!(transfers.active.get(&start.package).unwrap().base, Some(base));
+        }
+    }
+
+    #[test]
+    fn it_rejects_deltas_against_missing_packages() {
+        test_init!();
+        for i in 1..20 {
+            let (mut start, transfers, conf) = gen_start(i, 512, None);
+            let mut services = BackendServices::new();
+            if i % 2 == 0 {
+                services.full_package = Some("full_package".to_string());
+            }
+            let services = Mutex::new(services);
+            start.delta_base = Some(generate_random_package(i));
+
+            assert!(!start.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_starts_compressed_transfers() {
+        test_init!();
+        let (mut start, transfers, conf) = gen_start(10, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        start.encoding = Some("gzip".to_string());
+
+        assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+        let transfers = transfers.lock().unwrap();
+        assert_eq!(transfers.active.get(&start.package).unwrap().encoding, Encoding::Gzip);
+    }
+
+    #[test]
+    fn it_rejects_unsupported_encodings() {
+        test_init!();
+        let (mut start, transfers, conf) = gen_start(10, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        start.encoding = Some("zstd".to_string());
+
+        assert!(!start.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(transfers.lock().unwrap().active.is_empty());
+    }
+
+    #[test]
+    fn it_resumes_transfers_with_the_same_checksum() {
+        test_init!();
+        for i in 1..20 {
+            let (start, transfers, conf) = gen_start(i, 512, None);
+            let services = Mutex::new(BackendServices::new());
+
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.get_mut(&start.package).unwrap()
+                    .write_chunk("dGVzdAo=", 0, None).is_ok());
+
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            let transfers = transfers.lock().unwrap();
+            assert_eq!(transfers.active.get(&start.package).unwrap().transferred_chunks,
+                       vec!(0));
+        }
+    }
+
+    #[test]
+    fn it_restarts_transfers_with_a_different_checksum() {
+        test_init!();
+        for i in 1..20 {
+            let (mut start, transfers, conf) = gen_start(i, 512, None);
+            let services = Mutex::new(BackendServices::new());
+
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            assert!(transfers.lock().unwrap().active.get_mut(&start.package).unwrap()
+                    .write_chunk("dGVzdAo=", 0, None).is_ok());
+
+            start.checksum = "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca".to_string();
+            assert!(start.handle(&services, &transfers, "ignored", "", &conf));
+            let transfers = transfers.lock().unwrap();
+            let transfer = transfers.active.get(&start.package).unwrap();
+            assert_eq!(transfer.checksum, start.checksum);
+            assert!(transfer.transferred_chunks.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_queues_transfers_exceeding_the_limit() {
+        test_init!();
+        let (first, transfers, mut conf) = gen_start(10, 512, None);
+        let (second, _, _) = gen_start(11, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        services.lock().unwrap().deferred = Some("deferred".to_string());
+        conf.max_transfers = Some(1);
+
+        assert!(first.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(second.handle(&services, &transfers, "ignored", "", &conf));
+        let transfers = transfers.lock().unwrap();
+        assert_eq!(transfers.active.len(), 1);
+        assert_eq!(transfers.queued.len(), 1);
+        assert_eq!(transfers.queued[0].package, second.package);
+    }
+
+    #[test]
+    fn it_defers_transfers_exceeding_the_queue() {
+        test_init!();
+        let (first, transfers, mut conf) = gen_start(10, 512, None);
+        let (second, _, _) = gen_start(11, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        conf.max_transfers = Some(1);
+        conf.max_queued = Some(0);
+
+        assert!(first.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(!second.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(transfers.lock().unwrap().queued.is_empty());
+    }
+
+    #[test]
+    fn it_starts_queued_transfers_when_a_slot_frees_up() {
+        test_init!();
+        let (first, transfers, mut conf) = gen_start(10, 512, None);
+        let (second, _, _) = gen_start(11, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        conf.max_transfers = Some(1);
+
+        assert!(first.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(second.handle(&services, &transfers, "ignored", "", &conf));
+
+        let services = services.lock().unwrap();
+        let mut transfers = transfers.lock().unwrap();
+        start_queued(&services, &mut transfers, "ignored", "", &conf);
+        assert!(transfers.active.contains_key(&first.package));
+        assert_eq!(transfers.queued.len(), 1);
+
+        transfers.active.remove(&first.package);
+        start_queued(&services, &mut transfers, "ignored", "", &conf);
+        assert!(transfers.active.contains_key(&second.package));
+        assert!(transfers.queued.is_empty());
+    }
+
+    #[test]
+    fn it_queues_urgent_transfers_first() {
+        test_init!();
+        let (first, transfers, mut conf) = gen_start(10, 512, None);
+        let (optional, _, _) = gen_start(11, 512, None);
+        let (urgent, _, _) = gen_start(12, 512, None);
+        let services = Mutex::new(BackendServices::new());
+        conf.max_transfers = Some(1);
+        transfers.lock().unwrap().announced.insert(urgent.package.clone(), UserPackage {
+            package: urgent.package.clone(),
+            size: 512,
+            priority: Some(5),
+            mandatory: None,
+            deadline: None,
+            description: None,
+            requires: None
+        });
+
+        assert!(first.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(optional.handle(&services, &transfers, "ignored", "", &conf));
+        assert!(urgent.handle(&services, &transfers, "ignored", "", &conf));
+        let transfers = transfers.lock().unwrap();
+        assert_eq!(transfers.queued[0].package, urgent.package);
+        assert_eq!(transfers.queued[1].package, optional.package);
+    }
 }
@@ -0,0 +1,56 @@
+//! Handles "Uninstall Package" messages.
+
+use std::sync::Mutex;
+
+use message::{BackendServices, PackageId, Notification};
+use handler::{Transfers, HandleMessageParams};
+use configuration::ClientConfiguration;
+
+/// Type for "Uninstall Package" messages.
+#[derive(RustcDecodable)]
+pub struct UninstallParams {
+    /// The package to remove from the device.
+    pub package: PackageId
+}
+
+impl HandleMessageParams for UninstallParams {
+    fn handle(&self,
+              _: &Mutex<BackendServices>,
+              _: &Mutex<Transfers>,
+              _: &str, _: &str, _: &ClientConfiguration) -> bool {
+        info!("Server requested removal of {}", self.package);
+        true
+    }
+
+    fn get_message(&self) -> Option<Notification> {
+        Some(Notification::Uninstall(self.package.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::sync::Mutex;
+
+    use handler::{HandleMessageParams, Transfers};
+    use configuration::ClientConfiguration;
+    use message::Notification;
+
+    #[test]
+    fn it_forwards_the_package_to_the_main_loop() {
+        test_init!();
+        let services = Mutex::new(get_empty_backend());
+        let transfers = Mutex::new(Transfers::new_test());
+        let conf = ClientConfiguration::gen_test();
+        let package = generate_random_package(10);
+
+        let uninstall = UninstallParams { package: package.clone() };
+        assert!(uninstall.handle(&services, &transfers, "", "", &conf));
+        match uninstall.get_message() {
+            Some(Notification::Uninstall(p)) => assert_eq!(p, package),
+            _ => panic!("Expected a uninstall notification")
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Periodic heartbeats, telling the server that the client is alive and how busy it is.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::u32;
+
+use time;
+
+use rvi;
+use configuration::Configuration;
+use handler::Transfers;
+use message::Heartbeat;
+use outbox::Outbox;
+
+/// Seconds between two heartbeats, if no `heartbeat_interval` is configured.
+pub const DEFAULT_INTERVAL: i64 = 300;
+
+/// Start a thread, that sends a `Heartbeat` to the configured `heartbeat_service` every
+/// `heartbeat_interval` seconds. Both keys are read again before every heartbeat, so remote
+/// configuration takes effect with the next one. Without a service or with a interval of `0`,
+/// no heartbeats are sent until either changes.
+///
+/// # Arguments
+/// * `conf`: The shared configuration.
+/// * `rvi_url`: The full URL, where RVI can be reached.
+/// * `vin`: The VIN of this device.
+/// * `transfers`: The `Transfers` to report the pending transfers of.
+pub fn start(conf: Arc<Mutex<Configuration>>, rvi_url: String, vin: String,
+             transfers: Arc<Mutex<Transfers>>) {
+    let (outbox, service) = {
+        let conf = conf.lock().unwrap();
+        (Outbox::new(&conf.client.storage_dir), conf.client.heartbeat_service.clone())
+    };
+    if service.is_none() {
+        info!("No heartbeat_service configured, not sending heartbeats.");
+    }
+
+    let started = time::get_time().sec;
+    thread::spawn(move || {
+        loop {
+            let (service, interval) = {
+                let conf = conf.lock().unwrap();
+                (conf.client.heartbeat_service.clone(),
+                 conf.client.heartbeat_interval.unwrap_or(DEFAULT_INTERVAL))
+            };
+
+            match service {
+                Some(ref service) if interval > 0 => {
+                    let heartbeat = {
+                        let transfers = transfers.lock().unwrap();
+                        gen_heartbeat(&vin, time::get_time().sec - started, &transfers,
+                                      outbox.len())
+                    };
+                    if let Err(e) = rvi::send_message(&rvi_url, heartbeat, service) {
+                        warn!("Couldn't send heartbeat: {}", e);
+                    }
+                },
+                _ => {}
+            }
+
+            let wait = if interval > 0 { interval } else { DEFAULT_INTERVAL };
+            let wait = cmp::min(wait, u32::MAX as i64) as u32;
+            thread::sleep_ms(wait.saturating_mul(1000));
+        }
+    });
+}
+
+/// Create a `Heartbeat` for the current state of the client.
+///
+/// # Arguments
+/// * `vin`: The VIN of this device.
+/// * `uptime`: Seconds since the client started.
+/// * `transfers`: The active and queued `Transfers`.
+/// * `outbox`: The amount of reports waiting for delivery.
+fn gen_heartbeat(vin: &str, uptime: i64, transfers: &Transfers, outbox: usize) -> Heartbeat {
+    Heartbeat {
+        vin: vin.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime: uptime,
+        transfers: transfers.active.len() as u64,
+        queued: transfers.queued.len() as u64,
+        outbox: outbox as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::gen_heartbeat;
+    use test_library::*;
+
+    use std::sync::Arc;
+
+    use handler::Transfers;
+    use persistence::{Transfer, MemoryStorage};
+
+    #[test]
+    fn it_reports_the_pending_transfers() {
+        test_init!();
+        let mut transfers = Transfers::new_test();
+        for i in 1..4 {
+            let mut transfer = Transfer::new_test(Arc::new(MemoryStorage::new()));
+            let package = transfer.randomize(i);
+            transfers.active.insert(package, transfer);
+        }
+
+        let heartbeat = gen_heartbeat("V1234567890123456", 42, &transfers, 2);
+        assert_eq!(heartbeat.transfers, 3);
+        assert_eq!(heartbeat.queued, 0);
+        assert_eq!(heartbeat.outbox, 2);
+        assert_eq!(heartbeat.uptime, 42);
+        assert_eq!(&heartbeat.version, env!("CARGO_PKG_VERSION"));
+    }
+}
@@ -10,6 +10,8 @@ extern crate url;
 extern crate crypto;
 extern crate toml;
 extern crate dbus;
+extern crate libc;
+extern crate flate2;
 
 #[macro_use] extern crate log;
 extern crate env_logger;
@@ -60,10 +62,20 @@ macro_rules! try_msg_or {
 
 pub mod main_loop;
 pub mod configuration;
+pub mod offline;
+pub mod persistence;
+pub mod delta;
+pub mod logs;
 
 mod rvi;
 mod sota_dbus;
 mod jsonrpc;
 mod handler;
 mod message;
-mod persistence;
+mod outbox;
+mod cache;
+mod policy;
+mod maintenance;
+mod vehicle;
+mod campaign;
+mod heartbeat;
@@ -0,0 +1,219 @@
+//! Keeps the most recent log records of the client in a ring buffer, so the server can request
+//! them for diagnosing failed installations.
+//!
+//! Records are uploaded in chunks, mirroring the protocol the server uses to transfer packages:
+//! a "Start Upload" message with the amount of chunks and the checksum, the chunks themselves and
+//! a "Finish Upload" message.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use env_logger;
+use log;
+use log::{Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord, SetLoggerError};
+use rustc_serialize::base64;
+use rustc_serialize::base64::ToBase64;
+use time;
+
+#[cfg(not(test))] use rvi::send_message;
+#[cfg(test)] use rustc_serialize::Encodable;
+
+use message::{LogServices, LogUploadStart, LogChunk, LogUploadFinish};
+
+/// Amount of log records kept, if no `log_records` are configured.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// Maximum size of a uploaded chunk in bytes, before encoding.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Type for the ring buffer holding the most recent log records.
+pub struct LogBuffer {
+    /// The formatted records, oldest first.
+    records: VecDeque<String>,
+    /// The maximum amount of records to keep.
+    capacity: usize
+}
+
+impl LogBuffer {
+    /// Create a new, empty `LogBuffer`.
+    ///
+    /// # Arguments
+    /// * `capacity`: The maximum amount of records to keep.
+    pub fn new(capacity: usize) -> LogBuffer {
+        LogBuffer {
+            records: VecDeque::new(),
+            capacity: capacity
+        }
+    }
+
+    /// Add a record, dropping the oldest one if the buffer is full.
+    ///
+    /// # Arguments
+    /// * `record`: The formatted record.
+    pub fn push(&mut self, record: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Change the maximum amount of records to keep, dropping the oldest records if necessary.
+    ///
+    /// # Arguments
+    /// * `capacity`: The new maximum amount of records.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    /// Returns all records, oldest first, one per line.
+    pub fn contents(&self) -> String {
+        self.records.iter().fold(String::new(), |mut contents, record| {
+            contents.push_str(record);
+            contents.push('\n');
+            contents
+        })
+    }
+}
+
+/// Logger, that prints records as configured in `RUST_LOG` and keeps all records from `Info`
+/// upwards in a `LogBuffer`.
+struct RingLogger {
+    /// The logger printing the records.
+    inner: env_logger::Logger,
+    /// The buffer for uploading the records.
+    buffer: Arc<Mutex<LogBuffer>>
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= LogLevel::Info || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+        if record.level() <= LogLevel::Info {
+            let line = format!("{} {} {}: {}", time::now_utc().rfc3339(), record.level(),
+                               record.location().module_path(), record.args());
+            if let Ok(mut buffer) = self.buffer.lock() {
+                buffer.push(line);
+            }
+        }
+    }
+}
+
+/// Install the logger. Returns the `LogBuffer` it records to.
+///
+/// # Arguments
+/// * `capacity`: The maximum amount of records to keep.
+pub fn init(capacity: usize) -> Result<Arc<Mutex<LogBuffer>>, SetLoggerError> {
+    let buffer = Arc::new(Mutex::new(LogBuffer::new(capacity)));
+    let logger_buffer = buffer.clone();
+    try!(log::set_logger(|max_log_level| {
+        let inner = env_logger::Logger::new();
+        max_log_level.set(cmp::max(inner.filter(), LogLevelFilter::Info));
+        Box::new(RingLogger {
+            inner: inner,
+            buffer: logger_buffer
+        })
+    }));
+    Ok(buffer)
+}
+
+/// Upload `data` in chunks to the services the server provided. Returns a `String` with a error
+/// message, if any message couldn't be sent.
+///
+/// # Arguments
+/// * `rvi_url`: The full URL, where RVI can be reached.
+/// * `id`: The identifier the server assigned to the upload.
+/// * `services`: The services, the upload messages are sent to.
+/// * `vin`: The VIN of this device.
+/// * `data`: The logs to upload.
+pub fn upload(rvi_url: &str, id: &str, services: &LogServices, vin: &str, data: &[u8])
+    -> Result<(), String> {
+    let chunks: Vec<&[u8]> = data.chunks(CHUNK_SIZE).collect();
+    let mut hasher = Sha1::new();
+    hasher.input(data);
+
+    try!(send_message(rvi_url,
+                      LogUploadStart {
+                          id: id.to_string(),
+                          chunkscount: chunks.len() as u64,
+                          checksum: hasher.result_str(),
+                          vin: vin.to_string()
+                      }, &services.start));
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let bytes = chunk.to_base64(base64::Config {
+            char_set: base64::CharacterSet::UrlSafe,
+            newline: base64::Newline::LF,
+            pad: true,
+            line_length: None
+        });
+        try!(send_message(rvi_url,
+                          LogChunk {
+                              id: id.to_string(),
+                              index: i as u64 + 1,
+                              bytes: bytes,
+                              vin: vin.to_string()
+                          }, &services.chunk));
+    }
+
+    try!(send_message(rvi_url,
+                      LogUploadFinish {
+                          id: id.to_string(),
+                          vin: vin.to_string()
+                      }, &services.finish));
+    Ok(())
+}
+
+#[cfg(test)]
+fn send_message<E: Encodable>(url: &str, _: E, service: &str) -> Result<String, String> {
+    trace!("Would send log upload message to {} on {}", service, url);
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use message::LogServices;
+
+    #[test]
+    fn it_drops_the_oldest_records() {
+        test_init!();
+        let mut buffer = LogBuffer::new(2);
+        buffer.push("first".to_string());
+        buffer.push("second".to_string());
+        buffer.push("third".to_string());
+        assert_eq!(buffer.contents(), "second\nthird\n".to_string());
+
+        buffer.set_capacity(1);
+        assert_eq!(buffer.contents(), "third\n".to_string());
+        buffer.set_capacity(0);
+        buffer.push("fourth".to_string());
+        assert_eq!(buffer.contents(), "".to_string());
+    }
+
+    #[test]
+    fn it_uploads_logs_in_chunks() {
+        test_init!();
+        let services = LogServices {
+            start: "/sota/logs_start".to_string(),
+            chunk: "/sota/logs_chunk".to_string(),
+            finish: "/sota/logs_finish".to_string()
+        };
+        let data = vec!(b'a'; 40 * 1024);
+        upload("ignored", "upload-1", &services, "", &data).unwrap();
+    }
+}
@@ -2,13 +2,15 @@
 
 extern crate sota_client;
 #[macro_use] extern crate log;
-extern crate env_logger;
 extern crate getopts;
 
+use std::cmp;
 use std::env;
 use getopts::{Options, Matches};
 use sota_client::configuration::Configuration;
 use sota_client::main_loop;
+use sota_client::offline;
+use sota_client::logs;
 
 /// Helper function to print usage information to stdout.
 ///
@@ -39,6 +41,8 @@ fn match_args(args: &[String], program: &str) -> Matches {
                    reached", "URL");
     options.optopt("e", "edge", "explicitly set the host and port, where the \
                    client should listen for connections from RVI", "HOST:PORT");
+    options.optopt("i", "import", "install the packages of a local bundle and \
+                   exit, without connecting to RVI", "PATH");
 
     let matches = match options.parse(args) {
         Ok(m) => { m }
@@ -59,7 +63,7 @@ fn match_args(args: &[String], program: &str) -> Matches {
 /// Program entrypoint. Parses command line arguments and starts the main loop accordingly.
 #[cfg_attr(test, allow(dead_code))]
 fn main() {
-    env_logger::init().unwrap();
+    let log_buffer = logs::init(logs::DEFAULT_CAPACITY).unwrap();
     let args: Vec<String> = env::args().collect();
     let program: &str = &args[0];
     let matches = match_args(&args[1..], program);
@@ -74,6 +78,18 @@ fn main() {
         }
     };
 
+    if let Some(records) = configuration.client.log_records {
+        log_buffer.lock().unwrap().set_capacity(cmp::max(records, 0) as usize);
+    }
+
+    match matches.opt_str("i") {
+        Some(bundle) => {
+            let success = offline::import(&configuration, &bundle);
+            std::process::exit(if success { 0 } else { 1 });
+        },
+        None => {}
+    }
+
     let rvi_url: String = matches.opt_str("r")
         .unwrap_or(configuration.client.rvi_url.clone()
                    .unwrap_or("http://localhost:8901".to_string()));
@@ -81,5 +97,5 @@ fn main() {
         .unwrap_or(configuration.client.edge_url.clone()
                    .unwrap_or("localhost:9080".to_string()));
 
-    main_loop::start(&configuration, rvi_url, edge_url);
+    main_loop::start(&configuration, &conf_file, log_buffer, rvi_url, edge_url);
 }
@@ -1,51 +1,76 @@
 //! Main loop, starting the worker threads and wiring up communication channels between them.
 
-use std::sync::mpsc::channel;
+use std::cmp;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
 use std::ops::Deref;
 
+use time;
+
 use rvi;
-use handler::ServiceHandler;
-use message::{InitiateParams, BackendServices, PackageId};
-use message::{Notification, ServerPackageReport, LocalServices, ServerReport};
-use configuration::Configuration;
-use persistence::Transfer;
+use handler::{ServiceHandler, Transfers};
+use message::{InitiateParams, BackendServices};
+use message::{Notification, ServerPackageReport, LocalServices, ServerReport, ConfigReport};
+use message::{InstallDeferred, PackageId, PackageReport, UserPackage};
+use configuration::{Configuration, any_open};
+use outbox::Outbox;
+use persistence::{FileStorage, Storage};
+use cache;
+use campaign::Campaign;
+use policy;
+use maintenance;
+use vehicle;
 use sota_dbus;
+use logs;
+use heartbeat;
+use logs::LogBuffer;
+
+/// Seconds to wait before checking the vehicle preconditions of a postponed installation again, if
+/// no `retry_interval` is configured.
+const DEFAULT_RETRY_INTERVAL: i64 = 300;
 
 /// Main loop, starting the worker threads and wiring up communication channels between them.
 ///
 /// # Arguments
 /// * `conf`: A pointer to a `Configuration` object see the [documentation of the configuration
 ///   crate](../configuration/index.html).
+/// * `conf_file`: Path to the configuration file, remote configuration is stored next to it.
+/// * `log_buffer`: The `LogBuffer` of the installed logger, with the logs the server may request.
 /// * `rvi_url`: The URL, where RVI can be found, with the protocol.
 /// * `edge_url`: The `host:port` combination where the client should bind and listen for incoming
 ///   RVI calls.
-pub fn start(conf: &Configuration, rvi_url: String, edge_url: String) {
+pub fn start(conf: &Configuration, conf_file: &str, log_buffer: Arc<Mutex<LogBuffer>>,
+             rvi_url: String, edge_url: String) {
     // will receive RVI registration details
     let (tx_edge, rx_edge) = channel();
     let rvi_edge = rvi::ServiceEdge::new(rvi_url.clone(),
                                          edge_url.clone(),
                                          tx_edge);
 
-    // Holds metadata about running transfers
-    let transfers: Arc<Mutex<HashMap<PackageId, Transfer>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    // Holds metadata about running transfers, including the ones interrupted by a restart
+    let storage = Arc::new(FileStorage::new(&conf.client.storage_dir));
+    let transfers: Arc<Mutex<Transfers>> =
+        Arc::new(Mutex::new(Transfers::restore(storage.clone())));
+
+    cache::enforce_retention(&*storage, &conf.client, &[]);
+    cache::collect_garbage(&*storage, transfers.lock().unwrap().deref());
+
+    // remote configuration gets applied to the handlers, the timer and the heartbeat as well
+    let shared_conf = Arc::new(Mutex::new(conf.clone()));
+
+    // the server's services, that RVI announces as available
+    let connectivity = rvi::Connectivity::new();
 
     // will receive notifies from RVI and install requests from dbus
     let (tx_main, rx_main) = channel();
     let handler = ServiceHandler::new(transfers.clone(), tx_main.clone(),
-                                      rvi_url.clone(), conf.clone());
-
-    match conf.client.timeout {
-        Some(timeout) => {
-            let _ = thread::spawn(move || {
-                ServiceHandler::start_timer(transfers.deref(), timeout);
-            });
-        },
-        None => info!("No timeout configured, transfers will never time out.")
-    }
+                                      rvi_url.clone(), shared_conf.clone(),
+                                      connectivity.clone());
+    let deferred_downloads = maintenance::Deferred::new(tx_main.clone());
+    let deferred_installs = maintenance::Deferred::new(tx_main.clone());
 
     // these services will be registered with RVI. Keep in mind that you also have to write a
     // handler and forward messages to it, when introducing a new service.
@@ -54,30 +79,93 @@ pub fn start(conf: &Configuration, rvi_url: String, edge_url: String) {
                         "/sota/chunk",
                         "/sota/finish",
                         "/sota/getpackages",
-                        "/sota/abort");
+                        "/sota/abort",
+                        "/sota/uninstall",
+                        "/sota/config",
+                        "/sota/logs");
 
     thread::spawn(move || {
         rvi_edge.start(handler, services);
     });
 
     let dbus_receiver = sota_dbus::Receiver::new(conf.dbus.clone(),
-                                                 tx_main.clone());
+                                                 tx_main.clone(),
+                                                 connectivity);
     thread::spawn(move || {
         dbus_receiver.start();
     });
 
     let local_services = LocalServices::new(&rx_edge.recv().unwrap());
+    heartbeat::start(shared_conf.clone(), rvi_url.clone(),
+                     local_services.get_vin(conf.client.vin_match), transfers.clone());
     let mut backend_services = BackendServices::new();
+    let outbox = Outbox::new(&conf.client.storage_dir);
+    let mut campaigns: Vec<Campaign> = Vec::new();
+    let mut updates: HashMap<PackageId, UserPackage> = HashMap::new();
+    let mut postponed: HashSet<PackageId> = HashSet::new();
+    let mut aborted: HashSet<PackageId> = HashSet::new();
+    let mut conf = conf.clone();
 
     loop {
         match rx_main.recv().unwrap() {
-            // Pass on notifications to the DBus
+            // Pass on notifications to the DBus, most urgent first, and deliver queued reports,
+            // now that the server can be reached
             Notification::Notify(notify) => {
                 backend_services.update(&notify.services);
-                sota_dbus::send_notify(&conf.dbus, notify.packages);
+                let mut packages = notify.packages;
+                packages.sort_by(|a, b| a.cmp_urgency(b));
+                for package in packages.iter() {
+                    let _ = updates.insert(package.package.clone(), package.clone());
+                    let _ = aborted.remove(&package.package);
+                }
+
+                if Campaign::is_campaign(&packages) {
+                    match Campaign::new(&packages) {
+                        Ok(campaign) => {
+                            campaigns.retain(|c| !campaign.order().iter().any(|p| c.contains(p)));
+                            campaigns.push(campaign);
+                        },
+                        Err(e) => error!("Ignoring campaign: {}", e)
+                    }
+                }
+
+                // Initiate updates matching the policy, without waiting for the user
+                let now = time::now();
+                for package in packages.iter()
+                    .filter(|p| policy::auto_install(&conf.policy, p, &now)) {
+                    info!("Installing {} automatically, as it matches the policy",
+                          package.package);
+                    if let Err(e) = tx_main.send(Notification::Initiate(package.package.clone())) {
+                        error!("Couldn't initiate download: {}", e);
+                    }
+                }
+
+                sota_dbus::send_notify(&conf.dbus, packages);
+
+                outbox.flush(|report| {
+                    let server_report =
+                        ServerPackageReport::new(report, local_services
+                                                 .get_vin(conf.client.vin_match));
+                    match rvi::send_message(&rvi_url, server_report,
+                                            &backend_services.report) {
+                        Ok(..) => true,
+                        Err(e) => { error!("Couldn't send queued report: {}", e); false }
+                    }
+                });
             },
-            // Pass on initiate requests to RVI
+            // Pass on initiate requests to RVI, once downloads are allowed. Repeated requests for
+            // a deferred download are dropped, it gets initiated once its window opens.
             Notification::Initiate(packages) => {
+                if deferred_downloads.contains(&packages) {
+                    info!("Download of {} is already deferred", packages);
+                    continue;
+                }
+                if !any_open(&conf.maintenance.downloads, &time::now()) {
+                    info!("Deferring download of {} to the next maintenance window", packages);
+                    deferred_downloads.defer(conf.maintenance.downloads.clone(), packages,
+                                             Notification::Initiate);
+                    continue;
+                }
                 let initiate =
                     InitiateParams::new(packages, local_services.clone(),
                                         local_services
@@ -88,9 +176,117 @@ pub fn start(conf: &Configuration, rvi_url: String, edge_url: String) {
                     Err(e) => error!("Couldn't initiate download: {}", e)
                 }
             },
-            // Request and forward the installation report from DBus to RVI.
+            // Request and forward the installation report from DBus to RVI, once installations
+            // are allowed and all packages of its campaign are downloaded. The most urgent of the
+            // finished downloads is installed first. Repeated notifications for a deferred
+            // installation are dropped.
             Notification::Finish(package) => {
-                let report = sota_dbus::request_install(&conf.dbus, package);
+                let package = most_urgent(package, &rx_main, &tx_main, &updates);
+                if deferred_installs.contains(&package) {
+                    info!("Installation of {} is already deferred", package);
+                    continue;
+                }
+                if aborted.remove(&package) {
+                    info!("Not installing {}, as its campaign was aborted", package);
+                    continue;
+                }
+                let campaign = campaigns.iter().position(|c| c.contains(&package));
+                if let Some(i) = campaign {
+                    campaigns[i].downloaded(&package);
+                    if !campaigns[i].is_complete() {
+                        info!("Holding back installation of {} until its campaign is downloaded",
+                              package);
+                        continue;
+                    }
+                }
+
+                if !any_open(&conf.maintenance.installs, &time::now()) {
+                    info!("Deferring installation of {} to the next maintenance window", package);
+                    deferred_installs.defer(conf.maintenance.installs.clone(), package,
+                                            Notification::Finish);
+                    continue;
+                }
+                let ready = vehicle::check_preconditions(&conf.vehicle, conf.dbus.timeout);
+                if let Err(reason) = ready {
+                    let retry = cmp::max(conf.vehicle.retry_interval
+                                         .unwrap_or(DEFAULT_RETRY_INTERVAL), 1);
+                    info!("Postponing installation of {} for {} s: {}", package, retry, reason);
+                    // The server only hears about the first postponement of every package
+                    match backend_services.install_deferred {
+                        Some(ref service) if postponed.insert(package.clone()) => {
+                            let deferred = InstallDeferred {
+                                package: package.clone(),
+                                reason: reason,
+                                retry_after: retry,
+                                vin: local_services.get_vin(conf.client.vin_match)
+                            };
+                            if let Err(e) = rvi::send_message(&rvi_url, deferred, service) {
+                                error!("Couldn't report postponed installation: {}", e);
+                            }
+                        },
+                        _ => {}
+                    }
+                    deferred_installs.postpone(retry as u32, package, Notification::Finish);
+                    continue;
+                }
+                // Packages of pending campaigns must survive the retention policy
+                let pinned: Vec<PackageId> = campaigns.iter()
+                    .flat_map(|c| c.order().iter().cloned())
+                    .collect();
+                let packages = match campaign {
+                    Some(i) => campaigns.remove(i).order().to_vec(),
+                    None => vec!(package)
+                };
+                let vin = local_services.get_vin(conf.client.vin_match);
+
+                // Install in order and abort the rest of a campaign, once a package fails or one
+                // of its downloads got lost
+                let mut failed = match campaign {
+                    Some(_) => packages.iter().find(|p| !storage.has_package(p))
+                        .map(|p| format!("{} is missing", p)),
+                    None => None
+                };
+                for package in packages {
+                    let report = match failed.clone() {
+                        Some(reason) => ServerPackageReport::new(PackageReport {
+                            package: package,
+                            status: false,
+                            description: format!("Campaign aborted, as {}", reason),
+                            result: None
+                        }, vin.clone()),
+                        None => install(&*storage, &conf, &package, vin.clone(), &pinned)
+                    };
+                    if !report.status && failed.is_none() {
+                        failed = Some(format!("{} failed to install", report.package));
+                    }
+                    let _ = updates.remove(&report.package);
+                    let _ = postponed.remove(&report.package);
+
+                    match rvi::send_message(&rvi_url, report,
+                                            &backend_services.report) {
+                        Ok(..) => {},
+                        Err(e) => error!("Couldn't send report: {}", e)
+                    }
+                }
+            },
+            // Tell the Software Loading Manager about dropped downloads. The server was already
+            // informed by the timer or the finish handler, but gets a report for every other
+            // package of an aborted campaign.
+            Notification::DownloadFailed(package, reason) => {
+                let vin = local_services.get_vin(conf.client.vin_match);
+                for report in abort_campaign(&mut campaigns, &package, &mut aborted) {
+                    let _ = updates.remove(&report.package);
+                    match rvi::send_message(&rvi_url, ServerPackageReport::new(report, vin.clone()),
+                                            &backend_services.report) {
+                        Ok(..) => {},
+                        Err(e) => error!("Couldn't send report: {}", e)
+                    }
+                }
+                sota_dbus::send_download_failed(&conf.dbus, package, &reason);
+            },
+            // Request the removal of a package via DBus and forward the result to RVI
+            Notification::Uninstall(package) => {
+                let report = sota_dbus::request_uninstall(&conf.dbus, package);
                 let server_report =
                     ServerPackageReport::new(report, local_services
                                              .get_vin(conf.client.vin_match));
@@ -101,6 +297,45 @@ pub fn start(conf: &Configuration, rvi_url: String, edge_url: String) {
                     Err(e) => error!("Couldn't send report: {}", e)
                 }
             },
+            // Validate, store and apply a configuration fragment from the server and report the
+            // result to RVI. Fragments changing keys, that are only read at startup, are rejected.
+            Notification::Configure(fragment, service) => {
+                let (status, description) =
+                    match Configuration::update_remote(conf_file, &fragment) {
+                        Ok(new_conf) => {
+                            info!("Applied remote configuration");
+                            *shared_conf.lock().unwrap() = new_conf.clone();
+                            conf = new_conf;
+                            (true, "Configuration applied".to_string())
+                        },
+                        Err(e) => {
+                            error!("Rejecting remote configuration: {}", e);
+                            (false, e)
+                        }
+                    };
+                let report = ConfigReport {
+                    status: status,
+                    description: description,
+                    vin: local_services.get_vin(conf.client.vin_match)
+                };
+
+                match rvi::send_message(&rvi_url, report, &service) {
+                    Ok(..) => {},
+                    Err(e) => error!("Couldn't send configuration report: {}", e)
+                }
+            },
+            // Upload the buffered logs in the background, so installations aren't held up
+            Notification::UploadLogs(id, services) => {
+                let data = log_buffer.lock().unwrap().contents();
+                let rvi_url = rvi_url.clone();
+                let vin = local_services.get_vin(conf.client.vin_match);
+                thread::spawn(move || {
+                    match logs::upload(&rvi_url, &id, &services, &vin, data.as_bytes()) {
+                        Ok(..) => info!("Uploaded logs with upload id {}", id),
+                        Err(e) => error!("Couldn't upload logs: {}", e)
+                    }
+                });
+            },
             // Request a full report via DBus and forward it to RVI
             Notification::Report => {
                 let packages = sota_dbus::request_report(&conf.dbus);
@@ -117,3 +352,183 @@ pub fn start(conf: &Configuration, rvi_url: String, edge_url: String) {
         }
     }
 }
+
+/// Pick the most urgent of `package` and all downloads, that finished while the `main_loop` was
+/// busy, for installation. All other pending notifications are passed back to the `main_loop`,
+/// followed by the remaining finished downloads, most urgent first. Downloads without a announced
+/// update come last, in the order they finished.
+///
+/// # Arguments
+/// * `package`: The finished download, that is about to be handled.
+/// * `rx`: The `Receiver` of the `main_loop`.
+/// * `tx`: A `Sender` to pass notifications back to the `main_loop`.
+/// * `updates`: The updates announced by the server.
+fn most_urgent(package: PackageId, rx: &Receiver<Notification>, tx: &Sender<Notification>,
+               updates: &HashMap<PackageId, UserPackage>) -> PackageId {
+    let mut finished = vec!(package);
+    let mut pending = Vec::new();
+    while let Ok(notification) = rx.try_recv() {
+        match notification {
+            Notification::Finish(package) => finished.push(package),
+            other => pending.push(other)
+        }
+    }
+
+    finished.sort_by(|a, b| match (updates.get(a), updates.get(b)) {
+        (Some(a), Some(b)) => a.cmp_urgency(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal
+    });
+
+    let next = finished.remove(0);
+    let remaining = finished.into_iter().map(Notification::Finish);
+    for notification in pending.into_iter().chain(remaining) {
+        if let Err(e) = tx.send(notification) {
+            error!("{}", e);
+        }
+    }
+    next
+}
+
+/// Abort the campaign of `package`, after its download failed. Returns the failed reports for all
+/// other packages of the campaign. Those, that are still downloading, are added to `aborted`, so
+/// they won't be installed once they finish.
+///
+/// # Arguments
+/// * `campaigns`: The pending campaigns.
+/// * `package`: The package, whose download failed.
+/// * `aborted`: The downloads of aborted campaigns, that didn't finish yet.
+fn abort_campaign(campaigns: &mut Vec<Campaign>, package: &PackageId,
+                  aborted: &mut HashSet<PackageId>) -> Vec<PackageReport> {
+    let position = campaigns.iter().position(|c| c.contains(package));
+    let campaign = match position {
+        Some(i) => campaigns.remove(i),
+        None => return Vec::new()
+    };
+    warn!("Aborting the campaign of {}, as its download failed", package);
+
+    campaign.order().iter().filter(|p| *p != package).map(|member| {
+        if !campaign.is_downloaded(member) {
+            let _ = aborted.insert(member.clone());
+        }
+        PackageReport {
+            package: member.clone(),
+            status: false,
+            description: format!("Campaign aborted, as the download of {} failed", package),
+            result: None
+        }
+    }).collect()
+}
+
+/// Install `package` and reinstall the previously installed version, should the installation fail
+/// and the previous version still be cached. Returns the report for the server, including the
+/// outcome of the rollback.
+///
+/// # Arguments
+/// * `storage`: The storage holding the packages.
+/// * `conf`: The full configuration.
+/// * `package`: The package to install.
+/// * `vin`: The VIN of this device.
+/// * `pinned`: Packages, the retention policy must not delete.
+fn install(storage: &Storage, conf: &Configuration, package: &PackageId, vin: String,
+           pinned: &[PackageId]) -> ServerPackageReport {
+    let report = sota_dbus::request_install(&conf.dbus, package.clone());
+    cache::installed(storage, &conf.client, package, report.status, pinned);
+
+    // Only ask for the installed packages, once there is something to roll back
+    let rollback = if report.status {
+        None
+    } else {
+        let installed = sota_dbus::request_report(&conf.dbus);
+        cache::rollback_candidate(storage, &installed, package).map(|previous| {
+            warn!("Installation of {} failed, rolling back to {}", package, previous);
+            let rollback = sota_dbus::request_install(&conf.dbus, previous.clone());
+            if rollback.status {
+                cache::restored(storage, &conf.client, &previous, pinned);
+            }
+            rollback
+        })
+    };
+
+    let mut server_report = ServerPackageReport::new(report, vin);
+    server_report.rollback = rollback;
+    server_report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::collections::{HashMap, HashSet};
+    use std::sync::mpsc::channel;
+
+    use campaign::Campaign;
+    use message::{Notification, PackageId, UserPackage};
+
+    #[test]
+    fn it_installs_the_most_urgent_download_first() {
+        test_init!();
+        let (tx, rx) = channel();
+        let optional = generate_random_package(10);
+        let urgent = generate_random_package(11);
+        let unknown = generate_random_package(12);
+
+        let mut updates = HashMap::new();
+        for (package, priority) in vec!((optional.clone(), 0), (urgent.clone(), 5)) {
+            updates.insert(package.clone(), UserPackage {
+                package: package,
+                size: 500,
+                priority: Some(priority),
+                mandatory: None,
+                deadline: None,
+                description: None,
+                requires: None
+            });
+        }
+
+        tx.send(Notification::Report).unwrap();
+        tx.send(Notification::Finish(urgent.clone())).unwrap();
+        tx.send(Notification::Finish(optional.clone())).unwrap();
+        assert_eq!(most_urgent(unknown.clone(), &rx, &tx, &updates), urgent);
+
+        match rx.try_recv().unwrap() { Notification::Report => {}, _ => panic!("Lost report") }
+        for expected in vec!(optional, unknown) {
+            match rx.try_recv().unwrap() {
+                Notification::Finish(package) => assert_eq!(package, expected),
+                _ => panic!("Unexpected notification")
+            }
+        }
+    }
+
+    #[test]
+    fn it_aborts_the_campaign_of_a_failed_download() {
+        test_init!();
+        let gen = |i: usize, requires: Vec<PackageId>| UserPackage {
+            package: generate_random_package(i),
+            size: 500,
+            priority: None,
+            mandatory: None,
+            deadline: None,
+            description: None,
+            requires: Some(requires)
+        };
+        let base = gen(10, Vec::new());
+        let app = gen(11, vec!(base.package.clone()));
+        let plugin = gen(12, vec!(app.package.clone()));
+        let mut campaign = Campaign::new(&[base.clone(), app.clone(), plugin.clone()]).unwrap();
+        campaign.downloaded(&base.package);
+
+        let mut campaigns = vec!(campaign);
+        let mut aborted = HashSet::new();
+        let reports = abort_campaign(&mut campaigns, &app.package, &mut aborted);
+
+        assert!(campaigns.is_empty());
+        assert_eq!(reports.iter().map(|r| r.package.clone()).collect::<Vec<_>>(),
+                   vec!(base.package, plugin.package.clone()));
+        assert!(reports.iter().all(|r| !r.status));
+        assert_eq!(aborted.into_iter().collect::<Vec<_>>(), vec!(plugin.package));
+        assert!(abort_campaign(&mut campaigns, &app.package, &mut HashSet::new()).is_empty());
+    }
+}
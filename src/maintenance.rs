@@ -0,0 +1,154 @@
+//! Deferral of downloads and installations outside of the maintenance windows, configured in the
+//! `maintenance` section of the configuration, or while the vehicle isn't ready.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use time;
+
+use configuration::{TimeWindow, any_open};
+use message::{Notification, PackageId};
+
+/// Interval in which deferred notifications check, whether a window opened. In milliseconds.
+const POLL_INTERVAL: u32 = 60000;
+
+/// Keeps track of the packages, whose notification is held back, so every package is deferred
+/// only once and sent to the `main_loop` again exactly once. Use one instance per kind of
+/// notification.
+#[derive(Clone)]
+pub struct Deferred {
+    packages: Arc<Mutex<HashSet<PackageId>>>,
+    sender: Sender<Notification>
+}
+
+impl Deferred {
+    /// Create a new, empty `Deferred`.
+    ///
+    /// # Arguments
+    /// * `sender`: A `Sender` to the `main_loop`.
+    pub fn new(sender: Sender<Notification>) -> Deferred {
+        Deferred {
+            packages: Arc::new(Mutex::new(HashSet::new())),
+            sender: sender
+        }
+    }
+
+    /// Check whether the notification for `package` is currently held back.
+    ///
+    /// # Arguments
+    /// * `package`: The package to look for.
+    pub fn contains(&self, package: &PackageId) -> bool {
+        self.packages.lock().unwrap().contains(package)
+    }
+
+    /// Hold back the notification for `package` until one of `windows` opens and send it to the
+    /// `main_loop` again. Returns immediately, the waiting is done in a separate thread. Returns
+    /// `false` without doing anything, if `package` is already held back.
+    ///
+    /// # Arguments
+    /// * `windows`: The windows, in which the notification may be processed.
+    /// * `package`: The package, whose notification is deferred.
+    /// * `notification`: Builds the `Notification` for `package`, e.g. `Notification::Finish`.
+    pub fn defer<F>(&self, windows: Vec<TimeWindow>, package: PackageId, notification: F) -> bool
+        where F: FnOnce(PackageId) -> Notification + Send + 'static {
+        self.hold(package, notification, move || {
+            while !any_open(&windows, &time::now()) {
+                thread::sleep_ms(POLL_INTERVAL);
+            }
+        })
+    }
+
+    /// Hold back the notification for `package` for `delay` seconds and send it to the
+    /// `main_loop` again. Returns immediately, the waiting is done in a separate thread. Returns
+    /// `false` without doing anything, if `package` is already held back.
+    ///
+    /// # Arguments
+    /// * `delay`: Seconds to wait.
+    /// * `package`: The package, whose notification is postponed.
+    /// * `notification`: Builds the `Notification` for `package`, e.g. `Notification::Finish`.
+    pub fn postpone<F>(&self, delay: u32, package: PackageId, notification: F) -> bool
+        where F: FnOnce(PackageId) -> Notification + Send + 'static {
+        self.hold(package, notification, move || {
+            thread::sleep_ms(delay.saturating_mul(1000));
+        })
+    }
+
+    fn hold<F, W>(&self, package: PackageId, notification: F, wait: W) -> bool
+        where F: FnOnce(PackageId) -> Notification + Send + 'static,
+              W: FnOnce() + Send + 'static {
+        if !self.packages.lock().unwrap().insert(package.clone()) {
+            debug!("{} is already deferred", package);
+            return false;
+        }
+
+        let packages = self.packages.clone();
+        let sender = self.sender.clone();
+        let _ = thread::spawn(move || {
+            wait();
+            let _ = packages.lock().unwrap().remove(&package);
+            try_or!(sender.send(notification(package)), return);
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::generate_random_package;
+
+    use std::sync::mpsc::channel;
+
+    use message::Notification;
+
+    #[test]
+    fn it_resends_notifications_once_a_window_is_open() {
+        test_init!();
+        let (tx, rx) = channel();
+        let package = generate_random_package(10);
+        let deferred = Deferred::new(tx);
+        assert!(deferred.defer(vec!("00:00-24:00".parse().unwrap()), package.clone(),
+                               Notification::Initiate));
+
+        match rx.recv().unwrap() {
+            Notification::Initiate(p) => assert_eq!(p, package),
+            _ => panic!("Got wrong notification!")
+        }
+    }
+
+    #[test]
+    fn it_resends_postponed_notifications() {
+        test_init!();
+        let (tx, rx) = channel();
+        let package = generate_random_package(10);
+        let deferred = Deferred::new(tx);
+        assert!(deferred.postpone(0, package.clone(), Notification::Finish));
+
+        match rx.recv().unwrap() {
+            Notification::Finish(p) => assert_eq!(p, package),
+            _ => panic!("Got wrong notification!")
+        }
+    }
+
+    #[test]
+    fn it_defers_each_package_only_once() {
+        test_init!();
+        let (tx, rx) = channel();
+        let package = generate_random_package(10);
+        let deferred = Deferred::new(tx);
+        assert!(deferred.postpone(1, package.clone(), Notification::Finish));
+        assert!(deferred.contains(&package));
+        assert!(!deferred.postpone(1, package.clone(), Notification::Finish));
+        assert!(!deferred.defer(vec!("00:00-24:00".parse().unwrap()), package.clone(),
+                                Notification::Finish));
+
+        match rx.recv().unwrap() {
+            Notification::Finish(p) => assert_eq!(p, package),
+            _ => panic!("Got wrong notification!")
+        }
+        assert!(!deferred.contains(&package));
+        assert!(rx.try_recv().is_err());
+    }
+}
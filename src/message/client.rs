@@ -1,9 +1,10 @@
 //! Various messages and helper functions for them.
 
+use std::cmp::Ordering;
 use std::vec::Vec;
 use dbus::{Message, MessageItem, FromMessageItem, Error};
 use super::package_id::PackageId;
-use super::server::BackendServices;
+use super::server::{BackendServices, LogServices};
 
 /// Enumerates the different notification types, that are sent to the `main_loop`.
 pub enum Notification {
@@ -15,18 +16,60 @@ pub enum Notification {
     Report,
     /// Sent when a transfer is completed and ready to be installed.
     Finish(PackageId),
+    /// Sent when a transfer was dropped, as it timed out or the package couldn't be verified, with
+    /// the reason.
+    DownloadFailed(PackageId, String),
+    /// Sent when the server requested the removal of a package.
+    Uninstall(PackageId),
+    /// Sent when the server sent a validly signed configuration fragment, with the service the
+    /// result is reported to.
+    Configure(String, String),
+    /// Sent when the server requested the logs, with the upload id and the services to upload
+    /// them to.
+    UploadLogs(String, LogServices),
 }
 
-/// Encodes the package/size pair, that is sent by the server to notify the client of new updates.
+/// Encodes a package, that is sent by the server to notify the client of new updates, with its
+/// size and scheduling metadata.
 #[derive(RustcDecodable, Clone, PartialEq, Eq, Debug)]
 pub struct UserPackage {
     /// Name and version of the new package.
     pub package: PackageId,
     /// Size of the full transfers, with all dependent packages.
-    pub size: u64
+    pub size: u64,
+    /// Priority of the update. Higher values are more urgent, defaults to `0`.
+    pub priority: Option<u32>,
+    /// Whether the update has to be installed, e.g. for a safety recall. Defaults to `false`.
+    pub mandatory: Option<bool>,
+    /// Unix epoch timestamp, until which the update should be installed.
+    pub deadline: Option<i64>,
+    /// Human readable description of the update.
+    pub description: Option<String>,
+    /// Packages of the same notification, that have to be installed before this one. Makes the
+    /// notification a [`Campaign`](../campaign/struct.Campaign.html).
+    pub requires: Option<Vec<PackageId>>
+}
+
+impl UserPackage {
+    /// Compare the urgency of two updates. Mandatory updates come first, followed by higher
+    /// priorities and earlier deadlines. Updates without a deadline come last.
+    ///
+    /// # Arguments
+    /// * `other`: The update to compare with.
+    pub fn cmp_urgency(&self, other: &UserPackage) -> Ordering {
+        fn key(p: &UserPackage) -> (bool, u32, i64) {
+            (p.mandatory.unwrap_or(false), p.priority.unwrap_or(0),
+             p.deadline.unwrap_or(i64::max_value()))
+        }
+        let (mandatory, priority, deadline) = key(self);
+        let (other_mandatory, other_priority, other_deadline) = key(other);
+        (other_mandatory, other_priority, deadline)
+            .cmp(&(mandatory, priority, other_deadline))
+    }
 }
 
 impl From<UserPackage> for MessageItem {
+    /// Encodes the package and its size as `(a{ss}t)`, as expected by the "Notify" method.
     fn from(p: UserPackage) -> MessageItem {
         let package = MessageItem::from(&p.package);
         let size = MessageItem::from(p.size);
@@ -34,6 +77,20 @@ impl From<UserPackage> for MessageItem {
     }
 }
 
+impl UserPackage {
+    /// Encodes the package with its scheduling metadata as `(a{ss}tubxs)`, as expected by the
+    /// "NotifyUpdates" method. Unset values are sent as `0`, `false` or an empty string.
+    pub fn to_update_item(self) -> MessageItem {
+        let package = MessageItem::from(&self.package);
+        let size = MessageItem::from(self.size);
+        let priority = MessageItem::from(self.priority.unwrap_or(0));
+        let mandatory = MessageItem::from(self.mandatory.unwrap_or(false));
+        let deadline = MessageItem::from(self.deadline.unwrap_or(0));
+        let description = MessageItem::Str(self.description.unwrap_or(String::new()));
+        MessageItem::Struct(vec!(package, size, priority, mandatory, deadline, description))
+    }
+}
+
 /// Encodes the full message, that is sent to indicate updates and to provide the callback URLs to
 /// the server.
 pub struct UserMessage {
@@ -43,38 +100,131 @@ pub struct UserMessage {
     pub services: BackendServices
 }
 
+/// Maximum amount of bytes kept of the installer output. Longer output is truncated at the
+/// beginning, as the end usually holds the error.
+pub const MAX_INSTALLER_OUTPUT: usize = 4096;
+
+/// Enumerates the reasons, why the installer failed.
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq, Eq)]
+pub enum FailureCategory {
+    /// A dependency of the package is missing or has the wrong version.
+    Dependency,
+    /// The package didn't match its checksum.
+    Checksum,
+    /// There isn't enough space to install the package.
+    DiskSpace,
+    /// A installation script of the package failed.
+    ScriptError,
+    /// The installer reported a category, that isn't known to this client.
+    Other
+}
+
+impl<'a> From<&'a str> for FailureCategory {
+    fn from(category: &str) -> FailureCategory {
+        match category {
+            "dependency" => FailureCategory::Dependency,
+            "checksum" => FailureCategory::Checksum,
+            "disk_space" => FailureCategory::DiskSpace,
+            "script_error" => FailureCategory::ScriptError,
+            _ => FailureCategory::Other
+        }
+    }
+}
+
+/// Encodes the structured result of the installer.
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq, Eq)]
+pub struct InstallResult {
+    /// The result code of the installer, `0` on success.
+    pub code: i32,
+    /// The reason of the failure, if the installation failed.
+    pub category: Option<FailureCategory>,
+    /// The truncated standard output of the installer.
+    pub stdout: String,
+    /// The truncated standard error of the installer.
+    pub stderr: String
+}
+
+impl InstallResult {
+    /// Parse the result fields of a D-Bus installation report: the result code, the failure
+    /// category, which is empty on success, and the output of the installer.
+    ///
+    /// # Arguments
+    /// * `items`: The four result fields of the report.
+    fn from_items(items: &[MessageItem]) -> Result<InstallResult, ()> {
+        let code: i32 = try!(FromMessageItem::from(&items[0]));
+        let category: &String = try!(FromMessageItem::from(&items[1]));
+        let stdout: &String = try!(FromMessageItem::from(&items[2]));
+        let stderr: &String = try!(FromMessageItem::from(&items[3]));
+
+        let category = if category.is_empty() {
+            None
+        } else {
+            Some(FailureCategory::from(&category[..]))
+        };
+
+        Ok(InstallResult {
+            code: code,
+            category: category,
+            stdout: truncate_output(stdout),
+            stderr: truncate_output(stderr)
+        })
+    }
+}
+
+/// Keep the last `MAX_INSTALLER_OUTPUT` bytes of `output`.
+///
+/// # Arguments
+/// * `output`: The output to truncate.
+fn truncate_output(output: &str) -> String {
+    if output.len() <= MAX_INSTALLER_OUTPUT {
+        return output.to_string();
+    }
+
+    let mut start = output.len() - MAX_INSTALLER_OUTPUT;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    output[start..].to_string()
+}
+
 /// Encodes a installation report for a single package.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(RustcDecodable, RustcEncodable, Debug, PartialEq, Eq)]
 pub struct PackageReport {
     /// The package that was installed.
     pub package: PackageId,
     /// Boolean to indicate success or failure of the installation.
     pub status: bool,
     /// A short description of the result of the installation.
-    pub description: String
+    pub description: String,
+    /// The structured result of the installer. Missing, if the Software Loading Manager only
+    /// reports status and description.
+    pub result: Option<InstallResult>
 }
 
 impl<'a> FromMessageItem<'a> for PackageReport {
     fn from(i: &'a MessageItem) -> Result<Self, ()> {
-        let mut message = try!(match i {
-            &MessageItem::Struct(ref val) => Ok(val.clone()),
+        let message = try!(match i {
+            &MessageItem::Struct(ref val) => Ok(val),
             _ => Err(())
         });
+        if message.len() < 3 {
+            return Err(());
+        }
 
-        let description_item = try!(message.pop().ok_or(()));
-        let description: &String =
-            try!(FromMessageItem::from(&description_item));
-
-        let status_item = try!(message.pop().ok_or(()));
-        let status: bool = try!(FromMessageItem::from(&status_item));
-
-        let package_item = try!(message.pop().ok_or(()));
-        let package: PackageId = try!(FromMessageItem::from(&package_item));
+        let package: PackageId = try!(FromMessageItem::from(&message[0]));
+        let status: bool = try!(FromMessageItem::from(&message[1]));
+        let description: &String = try!(FromMessageItem::from(&message[2]));
+        let result = if message.len() >= 7 {
+            Some(try!(InstallResult::from_items(&message[3..7])))
+        } else {
+            None
+        };
 
         Ok(PackageReport {
             package: package,
             status: status,
-            description: description.clone()
+            description: description.clone(),
+            result: result
         })
     }
 }
@@ -104,7 +254,8 @@ impl ParsePackageReport for Message {
                 return PackageReport {
                     package: package,
                     status: false,
-                    description: "Missing argument to dbus call".to_string()
+                    description: "Missing argument to dbus call".to_string(),
+                    result: None
                 }
             }
         };
@@ -119,7 +270,8 @@ impl ParsePackageReport for Message {
                 PackageReport {
                     package: package,
                     status: false,
-                    description: "D-Bus parse error".to_string()
+                    description: "D-Bus parse error".to_string(),
+                    result: None
                 }
             }
         }
@@ -140,7 +292,8 @@ impl ParsePackageReport for Error {
         PackageReport {
             package: package,
             status: false,
-            description: message
+            description: message,
+            result: None
         }
     }
 }
@@ -148,28 +301,76 @@ impl ParsePackageReport for Error {
 #[cfg(test)]
 mod test {
     use dbus::*;
+    use std::iter;
 
     use super::*;
+    use super::truncate_output;
     use configuration::*;
     use test_library::generate_random_package;
 
     impl<'a> From<&'a PackageReport> for MessageItem {
         fn from(p: &PackageReport) -> MessageItem {
             let d: &str = &p.description;
-            MessageItem::Struct(vec!(
-                    MessageItem::from(&p.package),
-                    MessageItem::from(p.status),
-                    MessageItem::from(d)))
+            let mut items = vec!(
+                MessageItem::from(&p.package),
+                MessageItem::from(p.status),
+                MessageItem::from(d));
+            if let Some(ref r) = p.result {
+                let category = match r.category {
+                    Some(FailureCategory::Dependency) => "dependency",
+                    Some(FailureCategory::Checksum) => "checksum",
+                    Some(FailureCategory::DiskSpace) => "disk_space",
+                    Some(FailureCategory::ScriptError) => "script_error",
+                    Some(FailureCategory::Other) => "other",
+                    None => ""
+                };
+                let stdout: &str = &r.stdout;
+                let stderr: &str = &r.stderr;
+                items.push(MessageItem::from(r.code));
+                items.push(MessageItem::from(category));
+                items.push(MessageItem::from(stdout));
+                items.push(MessageItem::from(stderr));
+            }
+            MessageItem::Struct(items)
         }
     }
 
+    #[test]
+    fn it_decodes_structured_installer_results_from_dbus() {
+        let report = PackageReport {
+            package: generate_random_package(10),
+            status: false,
+            description: "Installation failed".to_string(),
+            result: Some(InstallResult {
+                code: 2,
+                category: Some(FailureCategory::DiskSpace),
+                stdout: "Unpacking".to_string(),
+                stderr: "No space left on device".to_string()
+            })
+        };
+
+        let message_item = MessageItem::from(&report);
+        let decoded: PackageReport = FromMessageItem::from(&message_item).unwrap();
+        assert_eq!(decoded, report);
+    }
+
+    #[test]
+    fn it_truncates_the_installer_output() {
+        let tail: String = iter::repeat('b').take(MAX_INSTALLER_OUTPUT).collect();
+        let output = format!("aaaaaaaaaa{}", tail);
+        assert_eq!(truncate_output(&output), tail);
+        assert_eq!(truncate_output("short"), "short".to_string());
+        assert_eq!(FailureCategory::from("unknown"), FailureCategory::Other);
+    }
+
     #[test]
     fn it_properly_decodes_a_successful_packge_report_from_dbus() {
         for i in 1..20 {
             let report = PackageReport {
                 package: generate_random_package(i),
                 status: true,
-                description: "Successfully installed package".to_string()
+                description: "Successfully installed package".to_string(),
+                result: None
             };
 
             let message_item = MessageItem::from(&report);
@@ -186,7 +387,8 @@ mod test {
             let report = PackageReport {
                 package: generate_random_package(i),
                 status: false,
-                description: "Some error".to_string()
+                description: "Some error".to_string(),
+                result: None
             };
 
             let message_item = MessageItem::from(&report);
@@ -204,7 +406,8 @@ mod test {
             let report = PackageReport {
                 package: package.clone(),
                 status: true,
-                description: "Successfully installed package".to_string()
+                description: "Successfully installed package".to_string(),
+                result: None
             };
 
             let config = DBusConfiguration::gen_test();
@@ -225,7 +428,8 @@ mod test {
             let report = PackageReport {
                 package: package.clone(),
                 status: false,
-                description: "Missing argument to dbus call".to_string()
+                description: "Missing argument to dbus call".to_string(),
+                result: None
             };
 
             let config = DBusConfiguration::gen_test();
@@ -247,10 +451,36 @@ mod test {
             let report = PackageReport {
                 package: package.clone(),
                 status: false,
-                description: format!("{}", error.message().unwrap())
+                description: format!("{}", error.message().unwrap()),
+                result: None
             };
 
             assert_eq!(error.parse(package), report);
         }
     }
+
+    fn gen_update(i: usize, priority: Option<u32>, mandatory: Option<bool>,
+                  deadline: Option<i64>) -> UserPackage {
+        UserPackage {
+            package: generate_random_package(i),
+            size: 500,
+            priority: priority,
+            mandatory: mandatory,
+            deadline: deadline,
+            description: None,
+            requires: None
+        }
+    }
+
+    #[test]
+    fn it_orders_updates_by_urgency() {
+        let optional = gen_update(10, None, None, None);
+        let due = gen_update(11, None, None, Some(1000));
+        let urgent = gen_update(12, Some(5), None, None);
+        let recall = gen_update(13, None, Some(true), None);
+
+        let mut updates = vec!(optional.clone(), due.clone(), urgent.clone(), recall.clone());
+        updates.sort_by(|a, b| a.cmp_urgency(b));
+        assert_eq!(updates, vec!(recall, urgent, due, optional));
+    }
 }
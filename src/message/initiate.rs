@@ -18,6 +18,12 @@ pub struct LocalServices {
     pub finish: String,
     /// "Get All Packages" URL.
     pub getpackages: String,
+    /// "Uninstall Package" URL.
+    pub uninstall: String,
+    /// "Update Configuration" URL.
+    pub config: String,
+    /// "Upload Logs" URL.
+    pub logs: String
 }
 
 impl LocalServices {
@@ -31,7 +37,10 @@ impl LocalServices {
             chunk: "".to_string(),
             abort: "".to_string(),
             finish: "".to_string(),
-            getpackages: "".to_string()
+            getpackages: "".to_string(),
+            uninstall: "".to_string(),
+            config: "".to_string(),
+            logs: "".to_string()
         };
 
         for service in s {
@@ -42,6 +51,9 @@ impl LocalServices {
                 "/sota/abort" => serv.abort = service.addr.clone(),
                 "/sota/finish" => serv.finish = service.addr.clone(),
                 "/sota/getpackages" => serv.getpackages = service.addr.clone(),
+                "/sota/uninstall" => serv.uninstall = service.addr.clone(),
+                "/sota/config" => serv.config = service.addr.clone(),
+                "/sota/logs" => serv.logs = service.addr.clone(),
                 _ => {}
             }
         }
@@ -1,11 +1,13 @@
 //! Helper functions for working with packages.
 
 use std::fmt;
+use std::str;
 use dbus::{FromMessageItem, MessageItem};
+use rustc_serialize::{Decodable, Decoder};
 use std::ops::Deref;
 
 /// Encodes a package, defined through a `name` and `version`.
-#[derive(RustcDecodable, RustcEncodable, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(RustcEncodable, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct PackageId {
     /// The name of the package.
     pub name: String,
@@ -13,12 +15,79 @@ pub struct PackageId {
     pub version: String
 }
 
+impl PackageId {
+    /// Check whether this `PackageId` is safe to use. Rejects empty names and versions, path
+    /// separators, relative path components and control characters. Returns a `String` with a
+    /// error message describing the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        for &(field, value) in [("name", &self.name), ("version", &self.version)].iter() {
+            if value.is_empty() {
+                return Err(format!("Package {} is empty", field));
+            }
+            if value == "." || value == ".." {
+                return Err(format!("Package {} '{}' is a relative path", field, value));
+            }
+            if value.chars().any(|c| c == '/' || c == '\\' || c.is_control()) {
+                return Err(format!("Package {} {:?} contains invalid characters",
+                                   field, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode this `PackageId` to a `String`, that can safely be used as a file name. The encoding
+    /// is reversible with [`from_path_component`](#method.from_path_component). Only ASCII
+    /// letters, digits and `._+~` are kept, all other bytes are percent encoded. Name and version
+    /// are separated by a `-`.
+    pub fn to_path_component(&self) -> String {
+        format!("{}-{}", encode_component(&self.name),
+                encode_component(&self.version))
+    }
+
+    /// Decode a `PackageId` from a `String` created by
+    /// [`to_path_component`](#method.to_path_component). Returns `None` if `s` isn't a valid
+    /// encoding.
+    ///
+    /// # Arguments
+    /// * `s`: The encoded `PackageId`.
+    pub fn from_path_component(s: &str) -> Option<PackageId> {
+        let mut parts = s.split('-');
+        let (name, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(version), None) => (name, version),
+            _ => return None
+        };
+
+        let package = PackageId {
+            name: match decode_component(name) { Some(n) => n, None => return None },
+            version: match decode_component(version) { Some(v) => v, None => return None }
+        };
+        package.validate().ok().map(|_| package)
+    }
+}
+
 impl fmt::Display for PackageId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}-{}", self.name, self.version)
     }
 }
 
+impl Decodable for PackageId {
+    fn decode<D: Decoder>(d: &mut D) -> Result<PackageId, D::Error> {
+        d.read_struct("PackageId", 2, |d| {
+            let name = try!(d.read_struct_field("name", 0, Decodable::decode));
+            let version = try!(d.read_struct_field("version", 1, Decodable::decode));
+            let package = PackageId {
+                name: name,
+                version: version
+            };
+            match package.validate() {
+                Ok(..) => Ok(package),
+                Err(e) => Err(d.error(&e))
+            }
+        })
+    }
+}
+
 impl<'a> From<&'a PackageId> for MessageItem {
     fn from(p: &PackageId) -> MessageItem {
         let n: &str = &p.name;
@@ -61,17 +130,72 @@ impl<'a> FromMessageItem<'a> for PackageId {
             }
         }
 
-        Ok(PackageId {
+        let package = PackageId {
             name: try!(name.ok_or(())),
             version: try!(version.ok_or(()))
-        })
+        };
+        try!(package.validate().map_err(|e| error!("{}", e)));
+        Ok(package)
     }
 }
 
+/// Percent encode all bytes of `s`, that are not ASCII letters, digits or one of `._+~`.
+///
+/// # Arguments
+/// * `s`: The `String` to encode.
+fn encode_component(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+            b'.' | b'_' | b'+' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b))
+        }
+    }
+    encoded
+}
+
+/// Decode a `String` encoded with `encode_component`. Returns `None` if `s` contains characters,
+/// that would have been encoded, or invalid escape sequences.
+///
+/// # Arguments
+/// * `s`: The `String` to decode.
+fn decode_component(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                if i + 3 > bytes.len() {
+                    return None;
+                }
+                let hex = match str::from_utf8(&bytes[i + 1..i + 3]) {
+                    Ok(h) => h,
+                    Err(..) => return None
+                };
+                decoded.push(match u8::from_str_radix(hex, 16) {
+                    Ok(b) => b,
+                    Err(..) => return None
+                });
+                i += 3;
+            },
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+            b'.' | b'_' | b'+' | b'~' => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+            _ => return None
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use dbus::*;
+    use rustc_serialize::json;
 
     #[test]
     fn it_properly_de_encodes_a_package_id_for_dbus() {
@@ -85,4 +209,74 @@ mod test {
 
         assert_eq!(decoded, package);
     }
+
+    #[test]
+    fn it_rejects_hostile_package_ids_from_dbus() {
+        let package = PackageId {
+            name: "../../etc".to_string(),
+            version: "passwd".to_string()
+        };
+
+        let message_item = MessageItem::from(&package);
+        let decoded: Result<PackageId, ()> = FromMessageItem::from(&message_item);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn it_rejects_hostile_package_ids_from_json() {
+        let hostile = ["{\"name\": \"../../etc/passwd\", \"version\": \"1\"}",
+                       "{\"name\": \"foo\", \"version\": \"../../../tmp\"}",
+                       "{\"name\": \"..\", \"version\": \"1\"}",
+                       "{\"name\": \"foo\", \"version\": \"..\"}",
+                       "{\"name\": \"\", \"version\": \"1\"}",
+                       "{\"name\": \"foo\\\\bar\", \"version\": \"1\"}",
+                       "{\"name\": \"foo\\u0000\", \"version\": \"1\"}",
+                       "{\"name\": \"foo\\n\", \"version\": \"1\"}"];
+        for msg in hostile.iter() {
+            assert!(json::decode::<PackageId>(msg).is_err(), "Accepted {}", msg);
+        }
+    }
+
+    #[test]
+    fn it_accepts_valid_package_ids_from_json() {
+        let package: PackageId =
+            json::decode("{\"name\": \"some-pkg\", \"version\": \"1.0-rc1\"}").unwrap();
+        assert_eq!(package.name, "some-pkg");
+        assert_eq!(package.version, "1.0-rc1");
+    }
+
+    #[test]
+    fn it_encodes_package_ids_as_safe_path_components() {
+        let names = ["some-pkg", "1.0-rc1", "a%b", "with space", "ünïcödé", "a.b_c+d~e",
+                     "..a", "...", "-", "%2F"];
+        for name in names.iter() {
+            for version in names.iter() {
+                let package = PackageId {
+                    name: name.to_string(),
+                    version: version.to_string()
+                };
+                let encoded = package.to_path_component();
+                assert!(!encoded.contains('/'));
+                assert!(encoded != "." && encoded != "..");
+                assert_eq!(encoded.matches('-').count(), 1);
+                assert_eq!(PackageId::from_path_component(&encoded), Some(package));
+            }
+        }
+    }
+
+    #[test]
+    fn it_keeps_simple_package_ids_readable() {
+        let package = PackageId {
+            name: "name".to_string(),
+            version: "1.0.2".to_string()
+        };
+        assert_eq!(package.to_path_component(), "name-1.0.2");
+    }
+
+    #[test]
+    fn it_rejects_invalid_path_components() {
+        for component in ["name", "a-b-c", "a-..", "a-%2", "a-%ZZ", "a-%2F", "a/b-1"].iter() {
+            assert_eq!(PackageId::from_path_component(component), None);
+        }
+    }
 }
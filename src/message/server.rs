@@ -1,15 +1,101 @@
 //! Translation layer for the SOTA server.
 
 use super::package_id::PackageId;
-use super::client::PackageReport;
+use super::client::{PackageReport, InstallResult};
+use configuration::ClientConfiguration;
 
 /// Encodes the "Chunk Received" message, indicating that a chunk was successfully transferred.
 #[derive(RustcEncodable)]
 pub struct ChunkReceived {
     /// The transfer to which the transferred chunk belongs.
     pub package: PackageId,
-    /// A list of the successfully transferred chunks.
+    /// A list of the successfully transferred chunks. Empty, if `ranges` are sent instead.
     pub chunks: Vec<u64>,
+    /// The successfully transferred chunks as inclusive `[first, last]` ranges.
+    pub ranges: Option<Vec<(u64, u64)>>,
+    /// How many chunks the server may send ahead of the first missing chunk, before waiting for
+    /// the next acknowledgement.
+    pub window: Option<u64>,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+impl ChunkReceived {
+    /// Create a new `ChunkReceived` message, encoded as configured in the `ack_format` and
+    /// `ack_window` keys of the `client` section.
+    ///
+    /// # Arguments
+    /// * `package`: The transfer to which the transferred chunks belong.
+    /// * `chunks`: The sorted indices of all successfully transferred chunks.
+    /// * `vin`: The VIN of this device.
+    /// * `conf`: The `client` section of the configuration.
+    pub fn new(package: PackageId, chunks: &[u64], vin: &str, conf: &ClientConfiguration)
+        -> ChunkReceived {
+        let ranges = conf.ack_format.as_ref().map(|f| f == "ranges").unwrap_or(false);
+        ChunkReceived {
+            package: package,
+            chunks: if ranges { Vec::new() } else { chunks.to_vec() },
+            ranges: if ranges { Some(to_ranges(chunks)) } else { None },
+            window: conf.ack_window.map(|w| if w < 1 { 1 } else { w as u64 }),
+            vin: vin.to_string()
+        }
+    }
+}
+
+/// Compress the sorted `chunks` to a list of inclusive `(first, last)` ranges.
+///
+/// # Arguments
+/// * `chunks`: The sorted and deduplicated indices to compress.
+pub fn to_ranges(chunks: &[u64]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &index in chunks {
+        if let Some(range) = ranges.last_mut() {
+            if range.1 + 1 == index {
+                range.1 = index;
+                continue;
+            }
+        }
+        ranges.push((index, index));
+    }
+    ranges
+}
+
+/// Encodes the "Chunk Rejected" message, indicating that a chunk was corrupted and needs to be
+/// retransmitted.
+#[derive(RustcEncodable)]
+pub struct ChunkRejected {
+    /// The transfer to which the rejected chunk belongs.
+    pub package: PackageId,
+    /// The index of the rejected chunk.
+    pub index: u64,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the "Full Package Required" message, asking the server to send the complete package,
+/// as the base of the announced delta isn't available on this device.
+#[derive(RustcEncodable)]
+pub struct FullPackageRequired {
+    /// The package, that should be transferred in full.
+    pub package: PackageId,
+    /// The base of the rejected delta.
+    pub base: PackageId,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the "Transfer Deferred" message, telling the server that a transfer can't be started
+/// now. The transfer was either queued and starts by itself once a slot frees up, or has to be
+/// retried by the server, as all transfer slots and the queue are occupied.
+#[derive(RustcEncodable)]
+pub struct TransferDeferred {
+    /// The package, that couldn't be started.
+    pub package: PackageId,
+    /// Seconds, after which the server should retry the transfer. For queued transfers, only if
+    /// the transfer didn't start by then.
+    pub retry_after: i64,
+    /// The position in the queue, counting from `0`, if the transfer was queued.
+    pub queued: Option<u64>,
     /// The VIN of this device.
     pub vin: String
 }
@@ -24,7 +110,16 @@ pub struct BackendServices {
     /// URL for the "Installation Report" call.
     pub report: String,
     /// URL for the "Get All Packages" call.
-    pub packages: String
+    pub packages: String,
+    /// URL for the "Full Package Required" call. Deltas against unavailable packages are reported
+    /// as failed installations, if the server doesn't provide it.
+    pub full_package: Option<String>,
+    /// URL for the "Transfer Deferred" call. Rejected transfers are reported as failed
+    /// installations and queued transfers aren't reported, if the server doesn't provide it.
+    pub deferred: Option<String>,
+    /// URL for the "Install Deferred" call. Postponed installations are only logged, if the
+    /// server doesn't provide it.
+    pub install_deferred: Option<String>
 }
 
 impl BackendServices {
@@ -34,7 +129,10 @@ impl BackendServices {
             start: "".to_string(),
             ack: "".to_string(),
             report: "".to_string(),
-            packages: "".to_string()
+            packages: "".to_string(),
+            full_package: None,
+            deferred: None,
+            install_deferred: None
         }
     }
 
@@ -47,6 +145,9 @@ impl BackendServices {
         self.ack = new.ack.clone();
         self.report = new.report.clone();
         self.packages = new.packages.clone();
+        self.full_package = new.full_package.clone();
+        self.deferred = new.deferred.clone();
+        self.install_deferred = new.install_deferred.clone();
     }
 }
 
@@ -69,7 +170,12 @@ pub struct ServerPackageReport {
     /// A short description of the result of the installation request.
     pub description: String,
     /// The VIN of this device.
-    pub vin: String
+    pub vin: String,
+    /// The result of reinstalling the previous version, if the installation failed and was rolled
+    /// back.
+    pub rollback: Option<PackageReport>,
+    /// The structured result of the installer, if the Software Loading Manager provided one.
+    pub result: Option<InstallResult>
 }
 
 impl ServerPackageReport {
@@ -83,11 +189,101 @@ impl ServerPackageReport {
             package: r.package,
             status: r.status,
             description: r.description,
-            vin: v
+            vin: v,
+            rollback: None,
+            result: r.result
         }
     }
 }
 
+/// Encodes the "Install Deferred" report, telling the server that a downloaded package will be
+/// installed later, as the vehicle isn't ready.
+#[derive(RustcEncodable)]
+pub struct InstallDeferred {
+    /// The package, whose installation was postponed.
+    pub package: PackageId,
+    /// The precondition, that wasn't met.
+    pub reason: String,
+    /// Seconds, after which the installation is retried.
+    pub retry_after: i64,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the result of a remote configuration update.
+#[derive(RustcEncodable)]
+pub struct ConfigReport {
+    /// Boolean to indicate, whether the configuration was applied.
+    pub status: bool,
+    /// A short description of the result, e.g. the validation error.
+    pub description: String,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the service URLs, that logs requested by the server are uploaded to.
+#[derive(RustcDecodable, Clone)]
+pub struct LogServices {
+    /// URL for the "Start Upload" call.
+    pub start: String,
+    /// URL for the "Log Chunk" call.
+    pub chunk: String,
+    /// URL for the "Finish Upload" call.
+    pub finish: String
+}
+
+/// Encodes the "Start Upload" message, announcing the logs that are about to be uploaded.
+#[derive(RustcEncodable)]
+pub struct LogUploadStart {
+    /// The identifier of the upload.
+    pub id: String,
+    /// The amount of chunks, that will be sent.
+    pub chunkscount: u64,
+    /// The SHA1 checksum of the full logs.
+    pub checksum: String,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the "Log Chunk" message, transferring a single chunk of the logs.
+#[derive(RustcEncodable)]
+pub struct LogChunk {
+    /// The identifier of the upload.
+    pub id: String,
+    /// The index of this chunk, starting at `1`.
+    pub index: u64,
+    /// The base64 encoded data of this chunk.
+    pub bytes: String,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the "Finish Upload" message, indicating that all chunks were sent.
+#[derive(RustcEncodable)]
+pub struct LogUploadFinish {
+    /// The identifier of the upload.
+    pub id: String,
+    /// The VIN of this device.
+    pub vin: String
+}
+
+/// Encodes the periodic "Heartbeat" message, telling the server that the client is alive.
+#[derive(RustcEncodable)]
+pub struct Heartbeat {
+    /// The VIN of this device.
+    pub vin: String,
+    /// The version of the client.
+    pub version: String,
+    /// Seconds since the client started.
+    pub uptime: i64,
+    /// The amount of active transfers.
+    pub transfers: u64,
+    /// The amount of transfers waiting for a free slot.
+    pub queued: u64,
+    /// The amount of installation reports waiting for delivery.
+    pub outbox: u64
+}
+
 /// Encodes a installed packages report, as required by the SOTA server.
 #[derive(RustcEncodable)]
 pub struct ServerReport {
@@ -110,3 +306,38 @@ impl ServerReport {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use configuration::ClientConfiguration;
+    use test_library::generate_random_package;
+
+    #[test]
+    fn it_compresses_chunks_to_ranges() {
+        assert_eq!(to_ranges(&[]), vec!());
+        assert_eq!(to_ranges(&[0, 1, 2, 3]), vec!((0, 3)));
+        assert_eq!(to_ranges(&[0, 1, 3, 5, 6, 9]), vec!((0, 1), (3, 3), (5, 6), (9, 9)));
+    }
+
+    #[test]
+    fn it_lists_chunks_by_default() {
+        let conf = ClientConfiguration::gen_test();
+        let ack = ChunkReceived::new(generate_random_package(10), &[0, 1, 2], "", &conf);
+        assert_eq!(ack.chunks, vec!(0, 1, 2));
+        assert_eq!(ack.ranges, None);
+        assert_eq!(ack.window, None);
+    }
+
+    #[test]
+    fn it_sends_ranges_and_windows_if_configured() {
+        let mut conf = ClientConfiguration::gen_test();
+        conf.ack_format = Some("ranges".to_string());
+        conf.ack_window = Some(8);
+        let ack = ChunkReceived::new(generate_random_package(10), &[0, 1, 2], "", &conf);
+        assert!(ack.chunks.is_empty());
+        assert_eq!(ack.ranges, Some(vec!((0, 2))));
+        assert_eq!(ack.window, Some(8));
+    }
+}
@@ -0,0 +1,323 @@
+//! Installation of packages from a local bundle, e.g. a USB stick, without any connection to the
+//! server.
+//!
+//! A bundle is a directory, holding a json manifest and a package for every update. The package
+//! is expected next to its manifest, with the same name and the extension `.spkg`, e.g.
+//! `update.json` and `update.spkg`.
+//!
+//! Packages are verified the same way as transferred packages, before they are handed to the
+//! Software Loading Manager. If a `bundle_key` is configured, every manifest has to carry a valid
+//! signature of its package, otherwise only unsigned manifests are accepted. The resulting
+//! installation reports are queued in the [`Outbox`](../outbox/struct.Outbox.html) and sent the
+//! next time the server contacts the client.
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crypto::ed25519;
+use rustc_serialize::hex::FromHex;
+use rustc_serialize::json;
+
+use cache;
+use configuration::Configuration;
+use message::{PackageId, PackageReport};
+use outbox::Outbox;
+use persistence::{Storage, FileStorage, PackageWriter};
+use sota_dbus;
+
+/// Type for the manifest describing a single package of a bundle.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Manifest {
+    /// The `PackageId` of the package.
+    pub package: PackageId,
+    /// The SHA1 checksum of the package.
+    pub checksum: String,
+    /// The hex encoded Ed25519 signature of the package name, version and checksum, each
+    /// followed by a newline.
+    pub signature: Option<String>
+}
+
+impl Manifest {
+    /// Verify the signature of the manifest against the public `key`. Returns a `String` with a
+    /// error message, if the signature is invalid, missing while a key is configured, or present
+    /// without a key to verify it.
+    ///
+    /// # Arguments
+    /// * `key`: The hex encoded public key, bundles have to be signed with.
+    pub fn verify(&self, key: Option<&str>) -> Result<(), String> {
+        let (signature, key) = match (self.signature.as_ref(), key) {
+            (None, None) => return Ok(()),
+            (None, Some(_)) => return Err(format!("Package {} isn't signed", self.package)),
+            (Some(_), None) => {
+                return Err(format!("Can't verify the signature of package {}, as no bundle_key \
+                                    is configured", self.package))
+            },
+            (Some(signature), Some(key)) => (signature, key)
+        };
+
+        let signature = try!(signature.from_hex()
+                             .map_err(|_| format!("Invalid signature of package {}",
+                                                  self.package)));
+        let key = try!(key.from_hex().map_err(|_| "Invalid bundle_key".to_string()));
+        if key.len() != 32 {
+            return Err("Invalid bundle_key".to_string());
+        }
+
+        let message = format!("{}\n{}\n{}\n", self.package.name, self.package.version,
+                              self.checksum);
+        if signature.len() == 64 && ed25519::verify(message.as_bytes(), &key, &signature) {
+            Ok(())
+        } else {
+            Err(format!("Invalid signature of package {}", self.package))
+        }
+    }
+}
+
+/// Install all packages found in the bundle at `path` and queue the installation reports. `path`
+/// can either point to a single manifest or to a directory with manifests. Returns `false`, if
+/// any of the packages couldn't be installed.
+///
+/// # Arguments
+/// * `conf`: The full `Configuration` of sota_client.
+/// * `path`: Path to the bundle.
+pub fn import(conf: &Configuration, path: &str) -> bool {
+    let manifests = try_or!(find_manifests(Path::new(path)), return false);
+    let outbox = Outbox::new(&conf.client.storage_dir);
+    let storage = FileStorage::new(&conf.client.storage_dir);
+    let mut success = true;
+
+    if manifests.is_empty() {
+        error!("No manifests found in {}", path);
+        return false;
+    }
+
+    for manifest in manifests {
+        let key = conf.client.bundle_key.as_ref().map(|k| &k[..]);
+        let report = match stage(&conf.client.storage_dir, key, &manifest) {
+            Ok(package) => {
+                info!("Installing package {} from {}", package, manifest.display());
+                let report = sota_dbus::request_install(&conf.dbus, package.clone());
+                cache::installed(&storage, &conf.client, &package, report.status, &[]);
+                report
+            },
+            Err((package, e)) => {
+                error!("{}", e);
+                match package {
+                    Some(package) => PackageReport {
+                        package: package,
+                        status: false,
+                        description: e,
+                        result: None
+                    },
+                    None => { success = false; continue; }
+                }
+            }
+        };
+
+        success = success && report.status;
+        try_or!(outbox.push(&report), success = false);
+    }
+
+    success
+}
+
+/// Copy the package described by the manifest at `path` to the storage directory and verify its
+/// signature and checksum. Returns the `PackageId` of the staged package on success. On failure a
+/// error message is returned, together with the `PackageId` if the manifest could be parsed.
+///
+/// # Arguments
+/// * `storage_dir`: Path where the client stores packages.
+/// * `key`: The hex encoded public key, bundles have to be signed with.
+/// * `path`: Path to the manifest.
+pub fn stage(storage_dir: &str, key: Option<&str>, path: &Path)
+    -> Result<PackageId, (Option<PackageId>, String)> {
+    let manifest = try!(read_manifest(path).map_err(|e| (None, e)));
+    let package = manifest.package.clone();
+    try!(manifest.verify(key).map_err(|e| (Some(package.clone()), e)));
+
+    let source = path.with_extension("spkg");
+    let storage = FileStorage::new(storage_dir);
+    try!(copy_package(&storage, &package, &source, &manifest.checksum)
+         .map_err(|e| (Some(package.clone()), e)));
+    Ok(package)
+}
+
+/// Copy the package at `source` to `storage` and commit it, if it matches `checksum`. Returns a
+/// `String` with a error message, should something go wrong.
+///
+/// # Arguments
+/// * `storage`: The storage to copy the package to.
+/// * `package`: The `PackageId` of the package.
+/// * `source`: Path to the package in the bundle.
+/// * `checksum`: The expected SHA1 checksum of the package.
+fn copy_package(storage: &Storage, package: &PackageId, source: &Path, checksum: &str)
+    -> Result<(), String> {
+    let mut file = try!(File::open(source)
+                        .map_err(|e| format!("Couldn't open {}: {}", source.display(), e)));
+    let mut writer = try!(PackageWriter::new(storage, package));
+    let mut buf = [0; 65536];
+
+    trace!("Copying {} to package {}", source.display(), package);
+    loop {
+        let read = try!(file.read(&mut buf)
+                        .map_err(|e| format!("Couldn't copy {}: {}", source.display(), e)));
+        if read == 0 {
+            break;
+        }
+        try!(writer.write(&buf[..read]));
+    }
+
+    writer.commit(checksum)
+}
+
+/// Find all manifests at `path`. Returns `path` itself, if it is a file, or all `.json` files in
+/// it, if it is a directory.
+///
+/// # Arguments
+/// * `path`: Path to the bundle.
+fn find_manifests(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let meta = try!(fs::metadata(path).map_err(|e| {
+        format!("Couldn't read bundle at '{}': {}", path.display(), e)
+    }));
+
+    if !meta.is_dir() {
+        return Ok(vec!(path.to_path_buf()));
+    }
+
+    let mut manifests = Vec::new();
+    for entry in try!(fs::read_dir(path).map_err(|e| format!("{}", e))) {
+        let entry = try!(entry.map_err(|e| format!("No entries: {}", e)));
+        let entry_path = entry.path();
+        if entry_path.extension().map(|e| e == "json").unwrap_or(false) {
+            manifests.push(entry_path);
+        }
+    }
+    manifests.sort();
+    Ok(manifests)
+}
+
+/// Read and decode the manifest at `path`.
+///
+/// # Arguments
+/// * `path`: Path to the manifest.
+fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let mut file = try!(OpenOptions::new().read(true).open(path)
+                        .map_err(|e| format!("Couldn't open file: {}", e)));
+    let mut data = String::new();
+    try!(file.read_to_string(&mut data)
+         .map_err(|e| format!("Couldn't read {}: {}", path.display(), e)));
+    json::decode(&data)
+        .map_err(|e| format!("Couldn't parse manifest {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::prelude::*;
+    use std::path::PathBuf;
+
+    use crypto::ed25519;
+    use rustc_serialize::hex::ToHex;
+    use rustc_serialize::json;
+
+    use message::PackageId;
+
+    fn gen_manifest(package: &PackageId, secret: &[u8]) -> Manifest {
+        let checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
+        let message = format!("{}\n{}\n{}\n", package.name, package.version, checksum);
+        Manifest {
+            package: package.clone(),
+            checksum: checksum,
+            signature: Some(ed25519::signature(message.as_bytes(), secret).to_hex())
+        }
+    }
+
+    #[test]
+    fn it_verifies_manifest_signatures() {
+        test_init!();
+        let (secret, public) = ed25519::keypair(&[7; 32]);
+        let key = public.to_hex();
+        let mut manifest = gen_manifest(&generate_random_package(15), &secret);
+        manifest.verify(Some(&key)).unwrap();
+
+        manifest.checksum = "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca".to_string();
+        assert!(manifest.verify(Some(&key)).is_err());
+    }
+
+    #[test]
+    fn it_refuses_signatures_it_cant_verify() {
+        test_init!();
+        let (secret, public) = ed25519::keypair(&[7; 32]);
+        let mut manifest = gen_manifest(&generate_random_package(15), &secret);
+        assert!(manifest.verify(None).is_err());
+
+        manifest.signature = None;
+        manifest.verify(None).unwrap();
+        assert!(manifest.verify(Some(&public.to_hex())).is_err());
+    }
+
+    fn write_bundle(prefix: &PathPrefix, package: &PackageId, checksum: &str)
+        -> PathBuf {
+        let mut dir = PathBuf::from(prefix.to_string());
+        dir.push("bundle");
+        fs::create_dir_all(&dir).unwrap();
+
+        let manifest = Manifest {
+            package: package.clone(),
+            checksum: checksum.to_string(),
+            signature: None
+        };
+        let mut path = dir.clone();
+        path.push("update.json");
+        OpenOptions::new().write(true).create(true).open(&path).unwrap()
+            .write_all(json::encode(&manifest).unwrap().as_bytes()).unwrap();
+        OpenOptions::new().write(true).create(true)
+            .open(path.with_extension("spkg")).unwrap()
+            .write_all(b"test\n").unwrap();
+        path
+    }
+
+    #[test]
+    fn it_stages_packages_with_matching_checksums() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let package = generate_random_package(15);
+        let manifest = write_bundle(&prefix, &package,
+                                    "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83");
+        let storage = format!("{}/storage", prefix);
+
+        assert_eq!(stage(&storage, None, &manifest).unwrap(), package);
+
+        let path = format!("{}/packages/{}-{}.spkg", storage,
+                           package.name, package.version);
+        let mut from_disk = Vec::new();
+        OpenOptions::new().open(PathBuf::from(path)).unwrap()
+            .read_to_end(&mut from_disk).unwrap();
+        assert_eq!(from_disk, b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_rejects_packages_with_mismatched_checksums() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let package = generate_random_package(15);
+        let manifest = write_bundle(&prefix, &package,
+                                    "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca");
+        let storage = format!("{}/storage", prefix);
+
+        match stage(&storage, None, &manifest) {
+            Ok(..) => panic!("Accepted package with invalid checksum!"),
+            Err((p, _)) => assert_eq!(p, Some(package.clone()))
+        }
+
+        let path = format!("{}/packages/{}-{}.spkg", storage,
+                           package.name, package.version);
+        assert!(fs::metadata(PathBuf::from(path)).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Persistent queue for installation reports, that couldn't be sent to the server yet.
+//!
+//! Reports are stored as json files in `storage_dir/outbox` and survive restarts of the client.
+//! They are sent and removed, the next time the server contacts the client. Files are named after
+//! a sequence number, that continues after the last queued report.
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use rustc_serialize::json;
+
+use message::PackageReport;
+
+/// Type for the queue of not yet delivered `PackageReport`s.
+pub struct Outbox {
+    /// Directory where the queued reports are stored.
+    dir: PathBuf
+}
+
+impl Outbox {
+    /// Create a new `Outbox`, that stores its reports below `storage_dir`.
+    ///
+    /// # Arguments
+    /// * `storage_dir`: Path where the client stores its data.
+    pub fn new(storage_dir: &str) -> Outbox {
+        let mut dir = PathBuf::from(storage_dir);
+        dir.push("outbox");
+        Outbox { dir: dir }
+    }
+
+    /// Queue a `PackageReport` for delivery. Returns a `String` with a error message, should
+    /// something go wrong.
+    ///
+    /// # Arguments
+    /// * `report`: The report to queue.
+    pub fn push(&self, report: &PackageReport) -> Result<(), String> {
+        try!(fs::create_dir_all(&self.dir).map_err(|e| {
+            format!("Couldn't create outbox at '{}': {}", self.dir.display(), e)
+        }));
+
+        let data = try!(json::encode(report).map_err(|e| format!("{}", e)));
+        let mut sequence = self.entries().last().map(|&(s, _)| s + 1).unwrap_or(0);
+
+        // Never overwrite a queued report, should another one take the same sequence number
+        let mut file;
+        loop {
+            let mut path = self.dir.clone();
+            path.push(format!("{:020}.json", sequence));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(f) => { file = f; break; },
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => sequence += 1,
+                Err(e) => return Err(format!("Couldn't open file: {}", e))
+            }
+        }
+        try!(file.write_all(data.as_bytes())
+             .map_err(|e| format!("Couldn't write report for {}: {}",
+                                  report.package, e)));
+
+        info!("Queued report for package {}", report.package);
+        Ok(())
+    }
+
+    /// Returns the number of queued reports.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Try to deliver all queued reports, in the order they were queued. Reports are removed from
+    /// the queue, when `send` returns `true`.
+    ///
+    /// # Arguments
+    /// * `send`: Function that delivers a single report and indicates success.
+    pub fn flush<F>(&self, mut send: F) where F: FnMut(PackageReport) -> bool {
+        for (_, path) in self.entries() {
+            let report = try_or!(read_report(&path), continue);
+            if send(report) {
+                try_or!(fs::remove_file(&path), continue);
+            }
+        }
+    }
+
+    /// Returns the sequence numbers and paths of all queued reports, in the order they were
+    /// queued. Files not named after a sequence number are ignored.
+    fn entries(&self) -> Vec<(u64, PathBuf)> {
+        let mut entries = Vec::new();
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(..) => return entries
+        };
+
+        for entry in dir {
+            let path = try_or!(entry, continue).path();
+            if path.extension().map(|e| e != "json").unwrap_or(true) {
+                continue;
+            }
+            let sequence = path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok());
+            match sequence {
+                Some(sequence) => entries.push((sequence, path)),
+                None => warn!("Ignoring unknown file {} in the outbox", path.display())
+            }
+        }
+        entries.sort();
+        entries
+    }
+}
+
+/// Read and decode the report stored at `path`. Returns a `String` with a error message, should
+/// something go wrong.
+///
+/// # Arguments
+/// * `path`: Pointer to the path of the stored report.
+fn read_report(path: &PathBuf) -> Result<PackageReport, String> {
+    let mut file = try!(OpenOptions::new().read(true).open(path)
+                        .map_err(|e| format!("Couldn't open file: {}", e)));
+    let mut data = String::new();
+    try!(file.read_to_string(&mut data)
+         .map_err(|e| format!("Couldn't read {}: {}", path.display(), e)));
+    json::decode(&data)
+        .map_err(|e| format!("Couldn't parse {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::fs;
+
+    use message::PackageReport;
+
+    fn gen_report(i: usize) -> PackageReport {
+        PackageReport {
+            package: generate_random_package(i),
+            status: true,
+            description: "Successfully installed package".to_string(),
+            result: None
+        }
+    }
+
+    #[test]
+    fn it_keeps_reports_until_they_are_sent() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let outbox = Outbox::new(&prefix.to_string());
+        for i in 1..10 {
+            outbox.push(&gen_report(i)).unwrap();
+        }
+        assert_eq!(outbox.len(), 9);
+
+        outbox.flush(|_| false);
+        assert_eq!(outbox.len(), 9);
+
+        let mut sent = Vec::new();
+        outbox.flush(|r| { sent.push(r); true });
+        assert_eq!(sent.len(), 9);
+        assert_eq!(outbox.len(), 0);
+    }
+
+    #[test]
+    fn it_continues_after_the_last_queued_report() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let outbox = Outbox::new(&prefix.to_string());
+        fs::create_dir_all(format!("{}/outbox", prefix)).unwrap();
+        fs::File::create(format!("{}/outbox/{:020}.json", prefix, 99)).unwrap();
+
+        outbox.push(&gen_report(10)).unwrap();
+        let entries = outbox.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, 100);
+    }
+
+    #[test]
+    fn it_delivers_reports_in_order() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let outbox = Outbox::new(&prefix.to_string());
+        let mut reports = Vec::new();
+        for i in 1..10 {
+            let report = gen_report(i);
+            outbox.push(&report).unwrap();
+            reports.push(report);
+        }
+
+        let mut sent = Vec::new();
+        outbox.flush(|r| { sent.push(r); true });
+        assert_eq!(sent, reports);
+    }
+}
@@ -0,0 +1,101 @@
+//! Encodings, the chunks of a transfer can be compressed with on the wire.
+
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+
+/// Type for the encoding of the transferred data of a [`Transfer`](struct.Transfer.html).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    /// The chunks hold the raw data.
+    Identity,
+    /// The chunks hold gzip compressed data. Either the whole package is compressed and split into
+    /// chunks, or every chunk is compressed on its own, as concatenated gzip members form a valid
+    /// gzip stream.
+    Gzip
+}
+
+impl Encoding {
+    /// Decode the concatenated `data` of all chunks. Returns the decoded data or a `String` with a
+    /// error message.
+    ///
+    /// # Arguments
+    /// * `data`: The concatenated data of all chunks.
+    pub fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match *self {
+            Encoding::Identity => Ok(data),
+            Encoding::Gzip => {
+                let mut decoder = try!(MultiGzDecoder::new(&data[..])
+                                       .map_err(|e| format!("Invalid gzip header: {}", e)));
+                let mut decoded = Vec::new();
+                try!(decoder.read_to_end(&mut decoded)
+                     .map_err(|e| format!("Couldn't decompress data: {}", e)));
+                Ok(decoded)
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s {
+            "identity" => Ok(Encoding::Identity),
+            "gzip" => Ok(Encoding::Gzip),
+            _ => Err(format!("Unsupported encoding '{}'", s))
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Encoding::Identity => write!(f, "identity"),
+            Encoding::Gzip => write!(f, "gzip")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn it_parses_supported_encodings() {
+        assert_eq!("identity".parse::<Encoding>(), Ok(Encoding::Identity));
+        assert_eq!("gzip".parse::<Encoding>(), Ok(Encoding::Gzip));
+        assert!("zstd".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn it_decompresses_gzip_data() {
+        let data = compress(b"test\n");
+        assert_eq!(Encoding::Gzip.decode(data).unwrap(), b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_decompresses_individually_compressed_chunks() {
+        let mut data = compress(b"te");
+        data.extend(compress(b"st\n"));
+        assert_eq!(Encoding::Gzip.decode(data).unwrap(), b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_rejects_invalid_gzip_data() {
+        assert!(Encoding::Gzip.decode(b"test\n".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,692 @@
+//! [`Storage`](trait.Storage.html) backend keeping chunks and packages in a directory on disk.
+//!
+//! Chunks are stored in `downloads/<package>/<index>`, the metadata of their transfer in
+//! `transfers/<package>.json` and assembled packages in `packages/<package>.spkg`. Packages are
+//! assembled in a temporary file and only moved to their final location, once they are synced to
+//! disk and verified. This way a software manager will never see a partially written package,
+//! even if the device loses power during assembly.
+
+use std::fs;
+use std::fs::{OpenOptions, DirEntry, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+use libc;
+
+use message::PackageId;
+use persistence::{Storage, StagedPackage, CachedPackage};
+
+/// Type for storing chunks and packages below a directory on disk.
+pub struct FileStorage {
+    /// Path to the directory, where chunks will be cached and finished packages will be stored.
+    prefix: String
+}
+
+impl FileStorage {
+    /// Return a new `FileStorage`.
+    ///
+    /// # Arguments
+    /// * `prefix`: Path where transferred chunks and assembled packages will be stored.
+    pub fn new(prefix: &str) -> FileStorage {
+        FileStorage { prefix: prefix.to_string() }
+    }
+
+    /// Get the full path for the specified chunk `index` of `package`.
+    ///
+    /// # Arguments
+    /// * `package`: The package the chunk belongs to.
+    /// * `index`: The index for which the path should be constructed.
+    fn chunk_path(&self, package: &PackageId, index: u64) -> PathBuf {
+        let mut path = chunk_dir(&self.prefix, package);
+        path.push(index.to_string());
+        path
+    }
+
+    /// Get the path of the file holding the transfer metadata of `package`.
+    ///
+    /// # Arguments
+    /// * `package`: The package the transfer belongs to.
+    fn transfer_path(&self, package: &PackageId) -> PathBuf {
+        let mut path = PathBuf::from(&self.prefix);
+        path.push("transfers");
+        path.push(format!("{}.json", package.to_path_component()));
+        path
+    }
+
+    /// Get the path of the directory below the storage directory with the given `name`.
+    ///
+    /// # Arguments
+    /// * `name`: Name of the directory.
+    fn dir(&self, name: &str) -> PathBuf {
+        let mut path = PathBuf::from(&self.prefix);
+        path.push(name);
+        path
+    }
+}
+
+impl Storage for FileStorage {
+    fn write_chunk(&self, package: &PackageId, index: u64, data: &[u8]) -> Result<(), String> {
+        let dir = chunk_dir(&self.prefix, package);
+        try!(fs::create_dir_all(&dir).map_err(|e| {
+            format!("Couldn't create chunk dir at '{}': {}", dir.display(), e)
+        }));
+
+        let path = self.chunk_path(package, index);
+        trace!("Saving chunk to {}", path.display());
+        write_new_file(&path, data)
+            .map_err(|e| format!("Couldn't write chunk {}: {}", path.display(), e))
+    }
+
+    fn read_chunk(&self, package: &PackageId, index: u64) -> Result<Vec<u8>, String> {
+        let path = self.chunk_path(package, index);
+        read_file(&path).map_err(|e| format!("Couldn't read chunk {}: {}", path.display(), e))
+    }
+
+    fn chunks(&self, package: &PackageId) -> Result<Vec<u64>, String> {
+        let dir = chunk_dir(&self.prefix, package);
+        if fs::metadata(&dir).is_err() {
+            return Ok(Vec::new());
+        }
+
+        // Make sure all indices are valid and sort them
+        let mut indices = Vec::new();
+        for entry in try!(read_dir(&dir)) {
+            let entry = try!(entry.map_err(|x| format!("No entries: {}", x)));
+            indices.push(try!(parse_index(entry)));
+        }
+        indices.sort();
+        Ok(indices)
+    }
+
+    fn remove_chunks(&self, package: &PackageId) -> Result<(), String> {
+        let transfer = self.transfer_path(package);
+        if fs::metadata(&transfer).is_ok() {
+            trace!("Dropping transfer metadata {}", transfer.display());
+            try_or!(fs::remove_file(&transfer), {});
+        }
+
+        let dir = chunk_dir(&self.prefix, package);
+        if fs::metadata(&dir).is_err() {
+            return Ok(());
+        }
+
+        for entry in try!(read_dir(&dir)) {
+            let entry = try_or!(entry, continue);
+            trace!("Dropping chunk file {}", entry.path().display());
+            try_or!(fs::remove_file(entry.path()), continue);
+        }
+
+        fs::remove_dir(&dir)
+            .map_err(|e| format!("Couldn't remove chunk dir '{}': {}", dir.display(), e))
+    }
+
+    fn write_transfer(&self, package: &PackageId, data: &[u8]) -> Result<(), String> {
+        let dir = self.dir("transfers");
+        try!(fs::create_dir_all(&dir).map_err(|e| {
+            format!("Couldn't create transfer dir at '{}': {}", dir.display(), e)
+        }));
+
+        let path = self.transfer_path(package);
+        write_new_file(&path, data)
+            .map_err(|e| format!("Couldn't write transfer {}: {}", path.display(), e))
+    }
+
+    fn read_transfer(&self, package: &PackageId) -> Result<Vec<u8>, String> {
+        let path = self.transfer_path(package);
+        read_file(&path).map_err(|e| format!("Couldn't read transfer {}: {}", path.display(), e))
+    }
+
+    fn downloads(&self) -> Result<Vec<PackageId>, String> {
+        let mut packages = Vec::new();
+        for path in read_entries(&self.dir("downloads")) {
+            let package = path.file_name()
+                .and_then(|s| s.to_str())
+                .and_then(PackageId::from_path_component);
+            if let Some(package) = package {
+                packages.push(package);
+            }
+        }
+
+        for path in read_entries(&self.dir("transfers")) {
+            if let Some(package) = parse_transfer_path(&path) {
+                if !packages.contains(&package) {
+                    packages.push(package);
+                }
+            }
+        }
+        Ok(packages)
+    }
+
+    fn stage_package(&self, package: &PackageId) -> Result<Box<StagedPackage>, String> {
+        let tmp_path = try!(tmp_package_path(&self.prefix, package));
+        let path = try!(package_path(&self.prefix, package));
+
+        trace!("Assembling package {} in {}", package, tmp_path.display());
+        let file = try!(OpenOptions::new()
+                        .write(true).create(true).truncate(true)
+                        .open(&tmp_path)
+                        .map_err(|x| format!("Couldn't open file: {}", x)));
+
+        Ok(Box::new(StagedFile {
+            file: file,
+            tmp_path: tmp_path,
+            path: path,
+            committed: false
+        }))
+    }
+
+    fn read_package(&self, package: &PackageId) -> Result<Vec<u8>, String> {
+        let path = try!(package_path(&self.prefix, package));
+        read_file(&path).map_err(|e| format!("Couldn't read package {}: {}", path.display(), e))
+    }
+
+    fn has_package(&self, package: &PackageId) -> bool {
+        package_path(&self.prefix, package)
+            .map(|path| fs::metadata(path).map(|m| m.is_file()).unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    fn packages(&self) -> Result<Vec<CachedPackage>, String> {
+        let dir = try!(package_dir(&self.prefix));
+        let mut packages = Vec::new();
+
+        for entry in try!(read_dir(&dir)) {
+            let entry = try_or!(entry, continue);
+            let path = entry.path();
+            let package = match parse_package_path(&path) {
+                Some(p) => p,
+                None => continue
+            };
+
+            let meta = try_or!(fs::metadata(&path), continue);
+            packages.push(CachedPackage {
+                package: package,
+                size: meta.len(),
+                used: try_or!(meta.modified(), continue)
+            });
+        }
+
+        Ok(packages)
+    }
+
+    fn touch_package(&self, package: &PackageId) -> Result<(), String> {
+        let path = try!(package_path(&self.prefix, package));
+        let c_path = try!(CString::new(path.to_string_lossy().into_owned())
+                          .map_err(|e| format!("{}", e)));
+        if unsafe { libc::utime(c_path.as_ptr(), ptr::null()) } != 0 {
+            return Err(format!("Couldn't update modification time of {}", path.display()));
+        }
+        Ok(())
+    }
+
+    fn remove_package(&self, package: &PackageId) -> Result<(), String> {
+        let path = try!(package_path(&self.prefix, package));
+        fs::remove_file(&path)
+            .map_err(|e| format!("Couldn't remove package {}: {}", path.display(), e))
+    }
+
+    fn remove_stray(&self) -> Result<(), String> {
+        for path in read_entries(&self.dir("downloads")) {
+            let is_download = path.file_name()
+                .and_then(|s| s.to_str())
+                .and_then(PackageId::from_path_component)
+                .is_some();
+            if !is_download {
+                info!("Removing stray download {}", path.display());
+                try_or!(remove_entry(&path), continue);
+            }
+        }
+
+        for path in read_entries(&self.dir("transfers")) {
+            let is_transfer = fs::symlink_metadata(&path).map(|m| m.is_file()).unwrap_or(false)
+                && parse_transfer_path(&path).is_some();
+            if !is_transfer {
+                info!("Removing stray transfer {}", path.display());
+                try_or!(remove_entry(&path), continue);
+            }
+        }
+
+        let packages = try!(package_dir(&self.prefix));
+        for path in read_entries(&packages) {
+            let is_package = fs::symlink_metadata(&path).map(|m| m.is_file()).unwrap_or(false)
+                && parse_package_path(&path).is_some();
+            if !is_package {
+                info!("Removing stray file {}", path.display());
+                try_or!(remove_entry(&path), continue);
+            }
+        }
+        Ok(())
+    }
+
+    fn used_space(&self) -> u64 {
+        dir_size(Path::new(&self.prefix))
+    }
+
+    fn free_space(&self) -> Result<u64, String> {
+        try!(fs::create_dir_all(&self.prefix).map_err(|e| {
+            format!("Couldn't create storage dir at '{}': {}", self.prefix, e)
+        }));
+        free_space(&self.prefix)
+    }
+}
+
+/// Type for a package, that is being assembled in a temporary file.
+struct StagedFile {
+    file: File,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    committed: bool
+}
+
+impl Write for StagedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl StagedPackage for StagedFile {
+    fn commit(mut self: Box<Self>) -> Result<(), String> {
+        try!(self.file.sync_all()
+             .map_err(|x| format!("Couldn't sync file {}: {}", self.tmp_path.display(), x)));
+
+        trace!("Saving package to {}", self.path.display());
+        try!(fs::rename(&self.tmp_path, &self.path)
+             .map_err(|x| format!("Couldn't move package to {}: {}", self.path.display(), x)));
+        self.committed = true;
+
+        // Sync the directory, so the rename is persisted
+        let dir = self.path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        File::open(&dir).and_then(|d| d.sync_all())
+            .map_err(|x| format!("Couldn't sync dir {}: {}", dir.display(), x))
+    }
+}
+
+impl Drop for StagedFile {
+    /// Remove the temporary file, if the package wasn't committed.
+    fn drop(&mut self) {
+        if !self.committed {
+            trace!("Discarding {}", self.tmp_path.display());
+            try_or!(fs::remove_file(&self.tmp_path), return);
+        }
+    }
+}
+
+/// Get the directory below `prefix`, where the chunks of `package` are cached. Doesn't create the
+/// directory.
+///
+/// # Arguments
+/// * `prefix`: Path where transferred chunks are stored.
+/// * `package`: The package to get the directory for.
+pub fn chunk_dir(prefix: &str, package: &PackageId) -> PathBuf {
+    let mut path = PathBuf::from(prefix);
+    path.push("downloads");
+    path.push(package.to_path_component());
+    path
+}
+
+/// Get the full path for the assembled `package` below `prefix`. Returns a
+/// [`PathBuf`](https://doc.rust-lang.org/stable/std/path/struct.PathBuf.html) on success or a
+/// `String` on errors detailing what went wrong.
+///
+/// # Arguments
+/// * `prefix`: Path where assembled packages are stored.
+/// * `package`: The package to get the path for.
+pub fn package_path(prefix: &str, package: &PackageId) -> Result<PathBuf, String> {
+    let mut path = try!(package_dir(prefix));
+    path.push(format!("{}.spkg", package.to_path_component()));
+    Ok(path)
+}
+
+/// Parse the `PackageId` from the path of a assembled package. Returns `None` if the path doesn't
+/// point to a package.
+///
+/// # Arguments
+/// * `path`: Path to the assembled package.
+pub fn parse_package_path(path: &Path) -> Option<PackageId> {
+    if path.extension().map(|e| e != "spkg").unwrap_or(true) {
+        return None;
+    }
+
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(PackageId::from_path_component)
+}
+
+/// Parse the `PackageId` from the path of the metadata of a transfer. Returns `None` if the path
+/// doesn't point to transfer metadata.
+///
+/// # Arguments
+/// * `path`: Path to the transfer metadata.
+fn parse_transfer_path(path: &Path) -> Option<PackageId> {
+    if path.extension().map(|e| e != "json").unwrap_or(true) {
+        return None;
+    }
+
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(PackageId::from_path_component)
+}
+
+/// Get the path of the temporary file below `prefix`, where `package` is assembled before it is
+/// moved to its final location. Returns a
+/// [`PathBuf`](https://doc.rust-lang.org/stable/std/path/struct.PathBuf.html) on success or a
+/// `String` on errors detailing what went wrong.
+///
+/// # Arguments
+/// * `prefix`: Path where assembled packages are stored.
+/// * `package`: The package to get the path for.
+fn tmp_package_path(prefix: &str, package: &PackageId) -> Result<PathBuf, String> {
+    let mut path = try!(package_dir(prefix));
+    path.push(format!(".{}.spkg.tmp", package.to_path_component()));
+    Ok(path)
+}
+
+/// Get the directory below `prefix`, where assembled packages are stored. Will create the
+/// directory if it doesn't exist. Returns a
+/// [`PathBuf`](https://doc.rust-lang.org/stable/std/path/struct.PathBuf.html) on success or a
+/// `String` on errors detailing what went wrong.
+///
+/// # Arguments
+/// * `prefix`: Path where assembled packages are stored.
+pub fn package_dir(prefix: &str) -> Result<PathBuf, String> {
+    let mut path = PathBuf::from(prefix);
+    path.push("packages");
+
+    fs::create_dir_all(&path).map_err(|e| {
+        let path_str = path.to_str().unwrap_or("unknown");
+        format!("Couldn't create packges dir at '{}': {}", path_str, e)
+    }).map(|_| path)
+}
+
+/// Returns the amount of bytes available to unprivileged users on the filesystem holding `path`
+/// or a `String` with a error message.
+///
+/// # Arguments
+/// * `path`: Path on the filesystem to check.
+fn free_space(path: &str) -> Result<u64, String> {
+    let c_path = try!(CString::new(path).map_err(|e| format!("{}", e)));
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(c_path.as_ptr(), &mut stat) != 0 {
+            return Err(format!("Couldn't get free space of '{}'", path));
+        }
+        Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+    }
+}
+
+/// Returns the size in bytes of all files below `path`. Unreadable entries are ignored.
+///
+/// # Arguments
+/// * `path`: The directory or file to measure.
+pub fn dir_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(..) => return 0
+    };
+
+    if !meta.is_dir() {
+        return meta.len();
+    }
+
+    let mut size = 0;
+    for entry in try_or!(fs::read_dir(path), return 0) {
+        let entry = try_or!(entry, continue);
+        size += dir_size(&entry.path());
+    }
+    size
+}
+
+/// Write the provided `data` to the file at `path`. Will create the file if it doesn't exist and
+/// overwrite existing files.
+///
+/// # Arguments
+/// * `path`: Pointer to a [`PathBuf`]
+///   (https://doc.rust-lang.org/stable/std/path/struct.PathBuf.html) where the data will be
+///   written to. Needs to point to a (possibly nonexistent) file.
+/// * `data`: The data to be written to disk.
+fn write_new_file(path: &PathBuf, data: &[u8]) -> io::Result<()> {
+    let mut file = try!(OpenOptions::new()
+                        .write(true).create(true)
+                        .truncate(true).open(path));
+
+    try!(file.write_all(data));
+    file.flush()
+}
+
+/// Read the whole file at `path`.
+///
+/// # Arguments
+/// * `path`: The file to read.
+fn read_file(path: &PathBuf) -> io::Result<Vec<u8>> {
+    let mut file = try!(OpenOptions::new().read(true).open(path));
+    let mut data = Vec::new();
+    try!(file.read_to_end(&mut data));
+    Ok(data)
+}
+
+/// Read the contents of a directory. Returns a
+/// [`ReadDir`](https://doc.rust-lang.org/stable/std/fs/struct.ReadDir.html) iterator on success or
+/// a `String` with a detailed error message on failure.
+fn read_dir(path: &PathBuf) -> Result<fs::ReadDir, String> {
+    fs::read_dir(path).map_err(|e| {
+        let path_str = path.to_str().unwrap_or("unknown");
+        format!("Couldn't read dir at '{}': {}", path_str, e)
+    })
+}
+
+/// Returns the paths of all entries of the directory at `path`, or an empty `Vector` if it can't
+/// be read.
+///
+/// # Arguments
+/// * `path`: The directory to read.
+fn read_entries(path: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(path) {
+        Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(..) => Vec::new()
+    }
+}
+
+/// Remove the file or directory at `path`.
+///
+/// # Arguments
+/// * `path`: The entry to remove.
+fn remove_entry(path: &Path) -> Result<(), String> {
+    let meta = try!(fs::symlink_metadata(path).map_err(|e| format!("{}", e)));
+    let result = if meta.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    result.map_err(|e| format!("Couldn't remove {}: {}", path.display(), e))
+}
+
+/// Parse a [`DirEntry`](https://doc.rust-lang.org/stable/std/fs/struct.DirEntry.html) to a `u64`.
+/// Returns the parsed number on success or a `String` with a detailed error message on failure.
+///
+/// # Arguments
+/// * `entry`: `DirEntry` to be parsed.
+fn parse_index(entry: DirEntry) -> Result<u64, String> {
+    let name = entry.file_name().into_string()
+        .unwrap_or("unknown".to_string());
+    u64::from_str(&name)
+        .map_err(|_| "Couldn't parse chunk index from filename".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::path::{Path, PathBuf};
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::prelude::*;
+
+    use message::PackageId;
+    use persistence::{Storage, PackageWriter, check_space};
+
+    fn read(path: String) -> Vec<u8> {
+        let mut from_disk = Vec::new();
+        OpenOptions::new()
+            .open(PathBuf::from(path))
+            .unwrap()
+            .read_to_end(&mut from_disk)
+            .unwrap();
+        from_disk
+    }
+
+    #[test]
+    fn it_writes_chunks_to_a_directory_per_package() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        for i in 1..20 {
+            let package = generate_random_package(i);
+            storage.write_chunk(&package, 3, b"test\n").unwrap();
+            storage.write_chunk(&package, 1, b"data").unwrap();
+
+            let path = format!("{}/downloads/{}-{}", prefix, package.name, package.version);
+            assert_eq!(read(format!("{}/3", path)), b"test\n".to_vec());
+            assert_eq!(storage.chunks(&package).unwrap(), vec!(1, 3));
+            assert_eq!(storage.read_chunk(&package, 1).unwrap(), b"data".to_vec());
+        }
+    }
+
+    #[test]
+    fn it_parses_package_paths() {
+        test_init!();
+        let package = parse_package_path(Path::new("/a/some%2Dname-1.0%2Drc1.spkg")).unwrap();
+        assert_eq!(package.name, "some-name");
+        assert_eq!(package.version, "1.0-rc1");
+        assert!(parse_package_path(Path::new("/a/name.spkg")).is_none());
+        assert!(parse_package_path(Path::new("/a/some-name-1.0.spkg")).is_none());
+        assert!(parse_package_path(Path::new("/a/name-1.0.tmp")).is_none());
+    }
+
+    #[test]
+    fn it_lists_downloads_with_chunks_or_transfer_metadata() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let chunked = generate_random_package(10);
+        let started = generate_random_package(11);
+        storage.write_chunk(&chunked, 0, b"test\n").unwrap();
+        storage.write_transfer(&chunked, b"{}").unwrap();
+        storage.write_transfer(&started, b"{}").unwrap();
+
+        let downloads = storage.downloads().unwrap();
+        assert_eq!(downloads.len(), 2);
+        assert!(downloads.contains(&chunked));
+        assert!(downloads.contains(&started));
+
+        storage.remove_chunks(&chunked).unwrap();
+        storage.read_transfer(&chunked).unwrap_err();
+        assert_eq!(storage.downloads().unwrap(), vec!(started));
+    }
+
+    #[test]
+    fn it_removes_the_chunk_directory() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        for i in 1..20 {
+            let package = generate_random_package(i);
+            storage.write_chunk(&package, 0, b"test\n").unwrap();
+            storage.remove_chunks(&package).unwrap();
+            assert!(storage.chunks(&package).unwrap().is_empty());
+        }
+
+        let path = PathBuf::from(format!("{}/downloads/", prefix));
+        for _ in fs::read_dir(&path).unwrap() {
+            panic!("Found non-empty directory!");
+        }
+    }
+
+    #[test]
+    fn it_keeps_hostile_package_ids_inside_the_storage_dir() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        for name in ["../../../tmp/evil", "/etc/passwd", "..", "a-b"].iter() {
+            let package = PackageId {
+                name: name.to_string(),
+                version: "../1".to_string()
+            };
+
+            let chunks = chunk_dir(&prefix.to_string(), &package);
+            assert_eq!(chunks.parent().unwrap(),
+                       Path::new(&format!("{}/downloads", prefix)));
+
+            let path = package_path(&prefix.to_string(), &package).unwrap();
+            assert_eq!(path.parent().unwrap(),
+                       Path::new(&format!("{}/packages", prefix)));
+        }
+    }
+
+    fn commit_package(checksum: &str) -> bool {
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let package = generate_random_package(20);
+
+        let mut writer = PackageWriter::new(&storage, &package).unwrap();
+        writer.write(b"test\n").unwrap();
+        let success = writer.commit(checksum).is_ok();
+
+        let path = format!("{}/packages/{}-{}.spkg", prefix, package.name, package.version);
+        assert_eq!(fs::metadata(&path).is_ok(), success);
+        if success {
+            assert_eq!(read(path), b"test\n".to_vec());
+        }
+        let tmp_path = format!("{}/packages/.{}-{}.spkg.tmp", prefix,
+                               package.name, package.version);
+        assert!(fs::metadata(&tmp_path).is_err());
+        success
+    }
+
+    #[test]
+    fn it_moves_verified_packages_into_place() {
+        test_init!();
+        assert!(commit_package("4e1243bd22c66e76c2ba9eddc1f91394e57f9f83"));
+    }
+
+    #[test]
+    fn it_leaves_no_trace_of_unverified_packages() {
+        test_init!();
+        assert!(!commit_package("fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca"));
+        assert!(!commit_package("invalid"));
+    }
+
+    #[test]
+    fn it_rejects_packages_exceeding_the_free_space() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let available = storage.free_space().unwrap();
+        check_space(&storage, available, None).unwrap_err();
+    }
+
+    #[test]
+    fn it_counts_transfers_and_packages_as_used_space() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        let storage = FileStorage::new(&prefix.to_string());
+        let package = generate_random_package(10);
+        storage.write_chunk(&package, 0, b"test\n").unwrap();
+        storage.write_transfer(&package, b"{}").unwrap();
+        let mut staged = storage.stage_package(&package).unwrap();
+        staged.write_all(b"package\n").unwrap();
+        staged.commit().unwrap();
+
+        assert_eq!(dir_size(Path::new(&format!("{}/transfers", prefix))), 2);
+        assert_eq!(dir_size(Path::new(&format!("{}/packages", prefix))), 8);
+        assert_eq!(storage.used_space(), 15);
+    }
+}
@@ -0,0 +1,241 @@
+//! [`Storage`](trait.Storage.html) backend keeping chunks and packages in memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::u64;
+
+use message::PackageId;
+use persistence::{Storage, StagedPackage, CachedPackage};
+
+/// Type for storing chunks and packages in memory. Useful for tests and devices, that don't have
+/// writable storage for packages.
+pub struct MemoryStorage {
+    /// The stored chunks, sorted by index.
+    chunks: Mutex<HashMap<PackageId, BTreeMap<u64, Vec<u8>>>>,
+    /// The metadata of the transfers.
+    transfers: Mutex<HashMap<PackageId, Vec<u8>>>,
+    /// The committed packages.
+    packages: Arc<Mutex<HashMap<PackageId, Vec<u8>>>>,
+    /// When the committed packages were last used.
+    used: Arc<Mutex<HashMap<PackageId, SystemTime>>>,
+    /// Maximum amount of bytes, this storage may hold.
+    capacity: u64
+}
+
+impl MemoryStorage {
+    /// Return a new, empty `MemoryStorage` without a capacity limit.
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::with_capacity(u64::MAX)
+    }
+
+    /// Return a new, empty `MemoryStorage`, that reports at most `capacity` bytes as free space.
+    ///
+    /// # Arguments
+    /// * `capacity`: Maximum amount of bytes, this storage may hold.
+    pub fn with_capacity(capacity: u64) -> MemoryStorage {
+        MemoryStorage {
+            chunks: Mutex::new(HashMap::new()),
+            transfers: Mutex::new(HashMap::new()),
+            packages: Arc::new(Mutex::new(HashMap::new())),
+            used: Arc::new(Mutex::new(HashMap::new())),
+            capacity: capacity
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write_chunk(&self, package: &PackageId, index: u64, data: &[u8]) -> Result<(), String> {
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.entry(package.clone()).or_insert(BTreeMap::new()).insert(index, data.to_vec());
+        Ok(())
+    }
+
+    fn read_chunk(&self, package: &PackageId, index: u64) -> Result<Vec<u8>, String> {
+        let chunks = self.chunks.lock().unwrap();
+        chunks.get(package).and_then(|c| c.get(&index)).cloned()
+            .ok_or(format!("No chunk {} stored for package {}", index, package))
+    }
+
+    fn chunks(&self, package: &PackageId) -> Result<Vec<u64>, String> {
+        let chunks = self.chunks.lock().unwrap();
+        Ok(chunks.get(package).map(|c| c.keys().cloned().collect()).unwrap_or(Vec::new()))
+    }
+
+    fn remove_chunks(&self, package: &PackageId) -> Result<(), String> {
+        let _ = self.chunks.lock().unwrap().remove(package);
+        let _ = self.transfers.lock().unwrap().remove(package);
+        Ok(())
+    }
+
+    fn write_transfer(&self, package: &PackageId, data: &[u8]) -> Result<(), String> {
+        let _ = self.transfers.lock().unwrap().insert(package.clone(), data.to_vec());
+        Ok(())
+    }
+
+    fn read_transfer(&self, package: &PackageId) -> Result<Vec<u8>, String> {
+        self.transfers.lock().unwrap().get(package).cloned()
+            .ok_or(format!("No transfer stored for package {}", package))
+    }
+
+    fn downloads(&self) -> Result<Vec<PackageId>, String> {
+        let mut packages: Vec<PackageId> = self.chunks.lock().unwrap().keys().cloned().collect();
+        for package in self.transfers.lock().unwrap().keys() {
+            if !packages.contains(package) {
+                packages.push(package.clone());
+            }
+        }
+        Ok(packages)
+    }
+
+    fn stage_package(&self, package: &PackageId) -> Result<Box<StagedPackage>, String> {
+        Ok(Box::new(StagedBuffer {
+            package: package.clone(),
+            data: Vec::new(),
+            packages: self.packages.clone(),
+            used: self.used.clone()
+        }))
+    }
+
+    fn read_package(&self, package: &PackageId) -> Result<Vec<u8>, String> {
+        self.packages.lock().unwrap().get(package).cloned()
+            .ok_or(format!("Package {} not found", package))
+    }
+
+    fn has_package(&self, package: &PackageId) -> bool {
+        self.packages.lock().unwrap().contains_key(package)
+    }
+
+    fn packages(&self) -> Result<Vec<CachedPackage>, String> {
+        let packages = self.packages.lock().unwrap();
+        let used = self.used.lock().unwrap();
+        Ok(packages.iter().map(|(package, data)| CachedPackage {
+            package: package.clone(),
+            size: data.len() as u64,
+            used: used.get(package).cloned().unwrap_or(SystemTime::now())
+        }).collect())
+    }
+
+    fn touch_package(&self, package: &PackageId) -> Result<(), String> {
+        if !self.has_package(package) {
+            return Err(format!("Package {} not found", package));
+        }
+        let _ = self.used.lock().unwrap().insert(package.clone(), SystemTime::now());
+        Ok(())
+    }
+
+    fn remove_package(&self, package: &PackageId) -> Result<(), String> {
+        let _ = self.used.lock().unwrap().remove(package);
+        self.packages.lock().unwrap().remove(package).map(|_| ())
+            .ok_or(format!("Package {} not found", package))
+    }
+
+    fn remove_stray(&self) -> Result<(), String> { Ok(()) }
+
+    fn used_space(&self) -> u64 {
+        let chunks = self.chunks.lock().unwrap();
+        let packages = self.packages.lock().unwrap();
+        let chunk_size: usize = chunks.values()
+            .flat_map(|c| c.values()).map(|d| d.len()).sum();
+        let package_size: usize = packages.values().map(|d| d.len()).sum();
+        (chunk_size + package_size) as u64
+    }
+
+    fn free_space(&self) -> Result<u64, String> {
+        Ok(self.capacity.saturating_sub(self.used_space()))
+    }
+}
+
+/// Type for a package, that is being assembled in memory.
+struct StagedBuffer {
+    package: PackageId,
+    data: Vec<u8>,
+    packages: Arc<Mutex<HashMap<PackageId, Vec<u8>>>>,
+    used: Arc<Mutex<HashMap<PackageId, SystemTime>>>
+}
+
+impl Write for StagedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl StagedPackage for StagedBuffer {
+    fn commit(self: Box<Self>) -> Result<(), String> {
+        let buffer = *self;
+        let _ = buffer.used.lock().unwrap().insert(buffer.package.clone(), SystemTime::now());
+        let _ = buffer.packages.lock().unwrap().insert(buffer.package, buffer.data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::io::Write;
+
+    use persistence::Storage;
+
+    #[test]
+    fn it_keeps_chunks_per_package() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+        let other = generate_random_package(11);
+        storage.write_chunk(&package, 3, b"3").unwrap();
+        storage.write_chunk(&package, 1, b"1").unwrap();
+        storage.write_chunk(&other, 2, b"2").unwrap();
+
+        assert_eq!(storage.chunks(&package).unwrap(), vec!(1, 3));
+        assert_eq!(storage.read_chunk(&package, 3).unwrap(), b"3".to_vec());
+        storage.read_chunk(&package, 2).unwrap_err();
+
+        storage.remove_chunks(&package).unwrap();
+        assert!(storage.chunks(&package).unwrap().is_empty());
+        assert_eq!(storage.chunks(&other).unwrap(), vec!(2));
+    }
+
+    #[test]
+    fn it_only_shows_committed_packages() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+
+        let mut staged = storage.stage_package(&package).unwrap();
+        staged.write_all(b"test").unwrap();
+        storage.read_package(&package).unwrap_err();
+        assert!(!storage.has_package(&package));
+        staged.commit().unwrap();
+        assert!(storage.has_package(&package));
+        assert_eq!(storage.read_package(&package).unwrap(), b"test".to_vec());
+    }
+
+    #[test]
+    fn it_removes_the_transfer_with_its_chunks() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+        storage.write_transfer(&package, b"{}").unwrap();
+        assert_eq!(storage.downloads().unwrap(), vec!(package.clone()));
+        assert_eq!(storage.read_transfer(&package).unwrap(), b"{}".to_vec());
+
+        storage.remove_chunks(&package).unwrap();
+        storage.read_transfer(&package).unwrap_err();
+        assert!(storage.downloads().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_reports_the_remaining_capacity() {
+        test_init!();
+        let storage = MemoryStorage::with_capacity(10);
+        storage.write_chunk(&generate_random_package(10), 0, b"test").unwrap();
+        assert_eq!(storage.free_space().unwrap(), 6);
+    }
+}
@@ -0,0 +1,289 @@
+//! Handles caching and storage for in-progress transfers and the assembly and verification of
+//! finished transfers.
+//!
+//! Chunks and packages are kept in a [`Storage`](trait.Storage.html) backend. The client uses
+//! [`FileStorage`](struct.FileStorage.html) below `storage_dir`, tests and integrators can plug in
+//! other backends, like the [`MemoryStorage`](struct.MemoryStorage.html).
+
+mod transfer;
+mod file;
+mod memory;
+mod encoding;
+
+use std::io::Write;
+use std::time::SystemTime;
+
+use crypto::sha1::Sha1;
+use crypto::digest::Digest;
+
+use message::PackageId;
+
+pub use self::transfer::{Transfer, ChunkError};
+pub use self::file::{FileStorage, chunk_dir, package_path, package_dir, parse_package_path};
+pub use self::memory::MemoryStorage;
+pub use self::encoding::Encoding;
+
+/// Trait for backends storing the chunks of in-progress transfers and assembled packages.
+pub trait Storage: Send + Sync {
+    /// Store the chunk with the given `index` of `package`. Overwrites existing chunks. Returns a
+    /// `String` with a error message, should something go wrong.
+    ///
+    /// # Arguments
+    /// * `package`: The package the chunk belongs to.
+    /// * `index`: Index of the chunk.
+    /// * `data`: The decoded data of the chunk.
+    fn write_chunk(&self, package: &PackageId, index: u64, data: &[u8]) -> Result<(), String>;
+
+    /// Read the chunk with the given `index` of `package`. Returns the data of the chunk or a
+    /// `String` with a error message.
+    ///
+    /// # Arguments
+    /// * `package`: The package the chunk belongs to.
+    /// * `index`: Index of the chunk.
+    fn read_chunk(&self, package: &PackageId, index: u64) -> Result<Vec<u8>, String>;
+
+    /// Returns the sorted indices of all stored chunks of `package` or a `String` with a error
+    /// message.
+    ///
+    /// # Arguments
+    /// * `package`: The package to list the chunks for.
+    fn chunks(&self, package: &PackageId) -> Result<Vec<u64>, String>;
+
+    /// Remove all stored chunks and the transfer metadata of `package`. Returns a `String` with a
+    /// error message, should something go wrong.
+    ///
+    /// # Arguments
+    /// * `package`: The package to remove the chunks for.
+    fn remove_chunks(&self, package: &PackageId) -> Result<(), String>;
+
+    /// Store the metadata of the transfer of `package`, so it can be restored after a restart.
+    /// Overwrites existing metadata. Returns a `String` with a error message, should something go
+    /// wrong.
+    ///
+    /// # Arguments
+    /// * `package`: The package the transfer belongs to.
+    /// * `data`: The serialized metadata.
+    fn write_transfer(&self, package: &PackageId, data: &[u8]) -> Result<(), String>;
+
+    /// Read the metadata of the transfer of `package`. Returns the serialized metadata or a
+    /// `String` with a error message.
+    ///
+    /// # Arguments
+    /// * `package`: The package the transfer belongs to.
+    fn read_transfer(&self, package: &PackageId) -> Result<Vec<u8>, String>;
+
+    /// Returns all packages with stored chunks or transfer metadata or a `String` with a error
+    /// message.
+    fn downloads(&self) -> Result<Vec<PackageId>, String>;
+
+    /// Start writing the assembled `package`. The package only becomes visible, once the returned
+    /// [`StagedPackage`](trait.StagedPackage.html) is committed.
+    ///
+    /// # Arguments
+    /// * `package`: The package to write.
+    fn stage_package(&self, package: &PackageId) -> Result<Box<StagedPackage>, String>;
+
+    /// Read the assembled `package`. Returns the data of the package or a `String` with a error
+    /// message.
+    ///
+    /// # Arguments
+    /// * `package`: The package to read.
+    fn read_package(&self, package: &PackageId) -> Result<Vec<u8>, String>;
+
+    /// Returns whether the assembled `package` is available in this storage.
+    ///
+    /// # Arguments
+    /// * `package`: The package to look for.
+    fn has_package(&self, package: &PackageId) -> bool;
+
+    /// Returns all assembled packages in this storage or a `String` with a error message.
+    fn packages(&self) -> Result<Vec<CachedPackage>, String>;
+
+    /// Mark the assembled `package` as used. Returns a `String` with a error message, should
+    /// something go wrong.
+    ///
+    /// # Arguments
+    /// * `package`: The package that was used.
+    fn touch_package(&self, package: &PackageId) -> Result<(), String>;
+
+    /// Remove the assembled `package`. Returns a `String` with a error message, should something
+    /// go wrong.
+    ///
+    /// # Arguments
+    /// * `package`: The package to remove.
+    fn remove_package(&self, package: &PackageId) -> Result<(), String>;
+
+    /// Remove everything from this storage, that is neither a download nor a assembled package,
+    /// e.g. leftovers of an interrupted assembly. Returns a `String` with a error message, should
+    /// the storage not be readable.
+    fn remove_stray(&self) -> Result<(), String>;
+
+    /// Returns the amount of bytes currently occupied by this storage.
+    fn used_space(&self) -> u64;
+
+    /// Returns the amount of bytes still available to this storage or a `String` with a error
+    /// message.
+    fn free_space(&self) -> Result<u64, String>;
+}
+
+/// Type for a assembled package in a [`Storage`](trait.Storage.html).
+#[derive(Debug, Clone)]
+pub struct CachedPackage {
+    /// The `PackageId` of the cached package.
+    pub package: PackageId,
+    /// Size of the package in bytes.
+    pub size: u64,
+    /// When the package was last used.
+    pub used: SystemTime
+}
+
+/// Trait for a package, that is being written to a [`Storage`](trait.Storage.html). Dropping a
+/// `StagedPackage` without committing it discards the written data.
+pub trait StagedPackage: Write {
+    /// Persist the written data and make the package visible under its final name. Returns a
+    /// `String` with a error message, should something go wrong.
+    fn commit(self: Box<Self>) -> Result<(), String>;
+}
+
+/// Writes a package to a [`Storage`](trait.Storage.html) and verifies its SHA1 checksum, before
+/// committing it.
+pub struct PackageWriter {
+    package: PackageId,
+    staged: Box<StagedPackage>,
+    hasher: Sha1
+}
+
+impl PackageWriter {
+    /// Start writing `package` to `storage`. Returns a `String` with a error message, should the
+    /// package not be stageable.
+    ///
+    /// # Arguments
+    /// * `storage`: The storage to write the package to.
+    /// * `package`: The package to write.
+    pub fn new(storage: &Storage, package: &PackageId) -> Result<PackageWriter, String> {
+        let staged = try!(storage.stage_package(package));
+        Ok(PackageWriter {
+            package: package.clone(),
+            staged: staged,
+            hasher: Sha1::new()
+        })
+    }
+
+    /// Append `data` to the package. Returns a `String` with a error message, should something go
+    /// wrong.
+    ///
+    /// # Arguments
+    /// * `data`: The data to append.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.hasher.input(data);
+        self.staged.write_all(data)
+            .map_err(|e| format!("Couldn't write to package {}: {}", self.package, e))
+    }
+
+    /// Verify the written data against `checksum` and commit the package on success. Discards the
+    /// package and returns a `String` with a error message otherwise.
+    ///
+    /// # Arguments
+    /// * `checksum`: The expected SHA1 checksum of the package.
+    pub fn commit(mut self, checksum: &str) -> Result<(), String> {
+        let hash = self.hasher.result_str();
+        if hash != checksum {
+            error!("Checksums didn't match for {}", self.package);
+            error!("    Expected: {}", checksum);
+            error!("    Got: {}", hash);
+            return Err("checksums didn't match".to_string());
+        }
+
+        trace!("Committing package {}", self.package);
+        self.staged.commit()
+    }
+}
+
+/// Space needed in addition to the package size, as the chunks and the assembled package are kept
+/// at the same time during assembly.
+const ASSEMBLY_OVERHEAD: u64 = 2;
+
+/// Check whether a package of `size` bytes can be transferred to `storage`. Takes the space
+/// needed for assembly and the optional `quota` for the whole storage into account. Returns a
+/// `String` with a error message detailing the missing space otherwise.
+///
+/// # Arguments
+/// * `storage`: The storage the package will be transferred to.
+/// * `size`: Size of the package in bytes.
+/// * `quota`: Maximum amount of bytes, the storage may occupy.
+pub fn check_space(storage: &Storage, size: u64, quota: Option<i64>) -> Result<(), String> {
+    let needed = size.saturating_mul(ASSEMBLY_OVERHEAD);
+
+    let available = try!(storage.free_space());
+    if needed > available {
+        return Err(format!("Not enough disk space: {} bytes needed, {} bytes available",
+                           needed, available));
+    }
+
+    match quota {
+        Some(quota) => {
+            let used = storage.used_space();
+            let quota = if quota < 0 { 0 } else { quota as u64 };
+            if used.saturating_add(needed) > quota {
+                Err(format!("Storage quota exceeded: {} bytes needed, {} of {} bytes used",
+                            needed, used, quota))
+            } else {
+                Ok(())
+            }
+        },
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    #[test]
+    fn it_accepts_packages_that_fit_into_the_quota() {
+        test_init!();
+        check_space(&MemoryStorage::new(), 1024, Some(2048)).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_packages_exceeding_the_quota() {
+        test_init!();
+        check_space(&MemoryStorage::new(), 1024, Some(2047)).unwrap_err();
+    }
+
+    #[test]
+    fn it_counts_stored_data_against_the_quota() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+        storage.write_chunk(&package, 0, b"test\n").unwrap();
+
+        assert_eq!(storage.used_space(), 5);
+        check_space(&storage, 1024, Some(2048)).unwrap_err();
+    }
+
+    #[test]
+    fn it_commits_packages_with_matching_checksums() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+        let mut writer = PackageWriter::new(&storage, &package).unwrap();
+        writer.write(b"test\n").unwrap();
+        writer.commit("4e1243bd22c66e76c2ba9eddc1f91394e57f9f83").unwrap();
+
+        assert_eq!(storage.read_package(&package).unwrap(), b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_discards_packages_with_mismatched_checksums() {
+        test_init!();
+        let storage = MemoryStorage::new();
+        let package = generate_random_package(10);
+        let mut writer = PackageWriter::new(&storage, &package).unwrap();
+        writer.write(b"test\n").unwrap();
+        writer.commit("fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca").unwrap_err();
+
+        storage.read_package(&package).unwrap_err();
+    }
+}
@@ -0,0 +1,587 @@
+//! Metadata of in-progress transfers and the assembly and verification of finished transfers.
+
+use std::str;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use time;
+
+use crypto::sha1::Sha1;
+use crypto::digest::Digest;
+
+#[cfg(test)] use rand;
+#[cfg(test)] use rand::Rng;
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json;
+
+use delta;
+use message::PackageId;
+use persistence::{Storage, PackageWriter, Encoding};
+
+/// Reasons, why a chunk couldn't be stored, with a description.
+#[derive(Debug, PartialEq)]
+pub enum ChunkError {
+    /// The chunk isn't valid base64 or doesn't match its checksum. It can be retransmitted.
+    Corrupted(String),
+    /// The chunk couldn't be written to the storage, e.g. as the disk is full. Retransmitting it
+    /// won't help.
+    Storage(String)
+}
+
+/// Type for storing the metadata of a in-progress transfer, which is defined as one package.
+/// Will clear out the stored chunks when freed.
+pub struct Transfer {
+    /// [`PackageId`](../message/struct.PackageId.html) of this transfer.
+    pub package: PackageId,
+    /// SHA1 checksum of the fully assembled package.
+    pub checksum: String,
+    /// The package, the transferred chunks are a [`delta`](../delta/index.html) against. The
+    /// chunks hold the full package, if this is `None`.
+    pub base: Option<PackageId>,
+    /// The [`Encoding`](enum.Encoding.html) of the transferred chunks.
+    pub encoding: Encoding,
+    /// `Vector` of transferred chunks.
+    pub transferred_chunks: Vec<u64>,
+    /// [`Storage`](trait.Storage.html), where chunks will be cached and finished packages will be
+    /// stored.
+    storage: Arc<Storage>,
+    /// Timestamp, when the last chunk was received. Given as a unix epoch timestamp.
+    pub last_chunk_received: i64,
+    /// The amount of chunks this transfer will have. `0` if unknown.
+    pub chunkscount: u64,
+    /// The amount of chunks received since the last acknowledgement.
+    pub unacked: u64,
+    /// Timestamp, when the received chunks were last acknowledged. Given as a unix epoch
+    /// timestamp.
+    pub last_ack: i64,
+    /// The first missing chunk at the last acknowledgement, where the window of the server
+    /// starts.
+    pub window_start: u64
+}
+
+/// Type for the metadata of a `Transfer`, that is kept in its [`Storage`](trait.Storage.html), so
+/// the transfer can be resumed after a restart.
+#[derive(RustcEncodable, RustcDecodable)]
+struct TransferState {
+    checksum: String,
+    base: Option<PackageId>,
+    encoding: String,
+    chunkscount: u64
+}
+
+impl Transfer {
+    /// Return a new `Transfer`
+    ///
+    /// # Arguments
+    /// * `storage`: Storage where transferred chunks and assembled package will be kept.
+    /// * `package`: [`PackageId`](../message/struct.PackageId.html) of this transfer.
+    /// * `checksum`: SHA1 checksum of the fully assembled package.
+    pub fn new(storage: Arc<Storage>, package: PackageId, checksum: String)
+        -> Transfer {
+        Transfer {
+            package: package,
+            checksum: checksum,
+            base: None,
+            encoding: Encoding::Identity,
+            transferred_chunks: Vec::new(),
+            storage: storage,
+            last_chunk_received: time::get_time().sec,
+            chunkscount: 0,
+            unacked: 0,
+            last_ack: time::get_time().sec,
+            window_start: 0
+        }
+    }
+
+    /// Restore the transfer of `package` from the metadata and chunks kept in `storage`. Returns a
+    /// `String` with a error message, if there is no valid metadata for the transfer.
+    ///
+    /// # Arguments
+    /// * `storage`: Storage where the transfer was persisted.
+    /// * `package`: [`PackageId`](../message/struct.PackageId.html) of the transfer.
+    pub fn restore(storage: Arc<Storage>, package: &PackageId) -> Result<Transfer, String> {
+        let data = try!(storage.read_transfer(package));
+        let data = try!(str::from_utf8(&data).map_err(|e| format!("{}", e)));
+        let state: TransferState = try!(json::decode(data).map_err(|e| {
+            format!("Invalid metadata for transfer of {}: {}", package, e)
+        }));
+        let encoding = try!(state.encoding.parse());
+        let chunks = try!(storage.chunks(package));
+
+        let mut transfer = Transfer::new(storage, package.clone(), state.checksum);
+        transfer.base = state.base;
+        transfer.encoding = encoding;
+        transfer.chunkscount = state.chunkscount;
+        transfer.transferred_chunks = chunks;
+        Ok(transfer)
+    }
+
+    /// Keep the metadata of this transfer in its [`Storage`](trait.Storage.html), so it can be
+    /// [`restore`](#method.restore)d after a restart. Returns a `String` with a error message,
+    /// should something go wrong.
+    pub fn persist(&self) -> Result<(), String> {
+        let state = TransferState {
+            checksum: self.checksum.clone(),
+            base: self.base.clone(),
+            encoding: self.encoding.to_string(),
+            chunkscount: self.chunkscount
+        };
+        let data = try!(json::encode(&state).map_err(|e| format!("{}", e)));
+        self.storage.write_transfer(&self.package, data.as_bytes())
+    }
+
+    /// Create a transfer with empty values. To be used in tests.
+    ///
+    /// # Arguments
+    /// * `storage`: Storage where transferred chunks and assembled package will be kept. This
+    ///   should be a [`MemoryStorage`](struct.MemoryStorage.html) or use a temporary directory for
+    ///   tests.
+    #[cfg(test)]
+    pub fn new_test(storage: Arc<Storage>) -> Transfer {
+        Transfer {
+            package: PackageId {
+                name: "".to_string(),
+                version: "".to_string()
+            },
+            checksum: "".to_string(),
+            base: None,
+            encoding: Encoding::Identity,
+            transferred_chunks: Vec::new(),
+            storage: storage,
+            last_chunk_received: time::get_time().sec,
+            chunkscount: 0,
+            unacked: 0,
+            last_ack: time::get_time().sec,
+            window_start: 0
+        }
+    }
+
+    /// Randomize a existing transfer, by creating a random
+    /// [`PackageId`](../message/struct.PackageId.html). Returns the created `PackageId`, so it can
+    /// be used in assertions.
+    ///
+    /// # Arguments
+    /// * `i`: Size of the name and version strings.
+    #[cfg(test)]
+    pub fn randomize(&mut self, i: usize) -> PackageId {
+        let name = rand::thread_rng()
+            .gen_ascii_chars().take(i).collect::<String>();
+        let version = rand::thread_rng()
+            .gen_ascii_chars().take(i).collect::<String>();
+
+        trace!("Testing with:");
+        trace!("  name: {}\n  version {}", name, version);
+
+        self.package.name = name.clone();
+        self.package.version = version.clone();
+
+        PackageId {
+            name: name,
+            version: version
+        }
+    }
+
+    /// Store a transferred chunk. Returns a [`ChunkError`](enum.ChunkError.html), telling corrupted
+    /// chunks, that can be retransmitted, from failures of the storage.
+    ///
+    /// # Arguments
+    /// * `msg`: Base64 encoded data of this chunk.
+    /// * `index`: Index of this chunk
+    /// * `checksum`: Optional SHA1 checksum of the decoded data of this chunk.
+    pub fn write_chunk(&mut self,
+                       msg: &str,
+                       index: u64,
+                       checksum: Option<&str>) -> Result<(), ChunkError> {
+        self.last_chunk_received = time::get_time().sec;
+
+        let data = try!(msg.from_base64().map_err(|e| {
+            ChunkError::Corrupted(format!("Couldn't decode chunk {} for package {}: {}",
+                                          index, self.package, e))
+        }));
+        if let Some(checksum) = checksum {
+            try!(verify_chunk(&data, checksum).map_err(|e| {
+                ChunkError::Corrupted(format!("Chunk {} for package {} is corrupted: {}",
+                                              index, self.package, e))
+            }));
+        }
+        try!(self.storage.write_chunk(&self.package, index, &data).map_err(|e| {
+            ChunkError::Storage(format!("Couldn't store chunk {} for package {}: {}",
+                                        index, self.package, e))
+        }));
+
+        self.transferred_chunks.push(index);
+        self.transferred_chunks.sort();
+        self.transferred_chunks.dedup();
+        Ok(())
+    }
+
+    /// Returns the index of the first chunk, that wasn't received yet.
+    pub fn first_missing(&self) -> u64 {
+        let mut next = 0;
+        for &index in self.transferred_chunks.iter() {
+            if index != next {
+                break;
+            }
+            next += 1;
+        }
+        next
+    }
+
+    /// Check whether all chunks of this transfer were received. Always `false`, if the amount of
+    /// chunks is unknown.
+    pub fn is_complete(&self) -> bool {
+        self.chunkscount > 0 && self.transferred_chunks.len() as u64 >= self.chunkscount
+    }
+
+    /// Assemble the transferred chunks to a package and verify it with the provided checksum.
+    /// Returns `false` and prints a error message if either the package can't be assembled or the
+    /// checksum doesn't match.
+    ///
+    /// The package is staged in the [`Storage`](trait.Storage.html) and only committed, once it
+    /// is verified. Compressed transfers are decoded and delta transfers are applied to their base
+    /// package first, so the checksum is always verified against the full, uncompressed package.
+    pub fn assemble_package(&self) -> bool {
+        trace!("Finalizing package {}", self.package);
+        let writer = try_or!(self.assemble_chunks(), return false);
+        try_or!(writer.commit(&self.checksum), return false);
+        true
+    }
+
+    /// Collect all chunks in order and write them to a newly staged package. Returns the
+    /// [`PackageWriter`](struct.PackageWriter.html) holding the staged package or a `String` with
+    /// a error message, should something go wrong.
+    fn assemble_chunks(&self) -> Result<PackageWriter, String> {
+        let mut writer = try!(PackageWriter::new(&*self.storage, &self.package));
+
+        if self.base.is_none() && self.encoding == Encoding::Identity {
+            for index in try!(self.storage.chunks(&self.package)) {
+                let chunk = try!(self.storage.read_chunk(&self.package, index));
+                try!(writer.write(&chunk));
+                trace!("Wrote chunk {} to package {}", index, self.package);
+            }
+            return Ok(writer);
+        }
+
+        let mut data = Vec::new();
+        for index in try!(self.storage.chunks(&self.package)) {
+            data.extend(try!(self.storage.read_chunk(&self.package, index)));
+        }
+
+        trace!("Decoding package {} from {}", self.package, self.encoding);
+        let data = try!(self.encoding.decode(data));
+        let package = match self.base {
+            Some(ref base) => {
+                trace!("Applying delta against {} to package {}", base, self.package);
+                let base_data = try!(self.storage.read_package(base));
+                try!(delta::apply(&base_data, &data).map_err(|e| {
+                    format!("Couldn't apply delta against {}: {}", base, e)
+                }))
+            },
+            None => data
+        };
+        try!(writer.write(&package));
+
+        Ok(writer)
+    }
+}
+
+/// Verify the SHA1 checksum of the decoded `data` of a single chunk. Returns a `String` showing
+/// the mismatched checksums on errors.
+///
+/// # Arguments
+/// * `data`: The decoded data of the chunk.
+/// * `checksum`: The expected SHA1 checksum.
+fn verify_chunk(data: &[u8], checksum: &str) -> Result<(), String> {
+    let mut hasher = Sha1::new();
+    hasher.input(data);
+    let hash = hasher.result_str();
+
+    if hash == checksum {
+        Ok(())
+    } else {
+        Err(format!("checksum {} doesn't match expected {}", hash, checksum))
+    }
+}
+
+impl Drop for Transfer {
+    /// When a `Transfer` is freed it will also clear out the associated chunks in the storage.
+    fn drop(&mut self) {
+        trace!("Dropping transfer for package {}", self.package);
+        try_or!(self.storage.remove_chunks(&self.package), return);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_library::*;
+
+    use std::sync::Arc;
+
+    use crypto::sha1::Sha1;
+    use crypto::digest::Digest;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use rand;
+    use rand::Rng;
+    use rustc_serialize::base64;
+    use rustc_serialize::base64::ToBase64;
+
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
+    use persistence::{Storage, FileStorage, MemoryStorage, Encoding};
+
+    macro_rules! assert_chunk_written {
+        ($transfer:ident,
+         $storage:ident,
+         $package:ident,
+         $index:ident,
+         $data:ident) => {{
+            trace!("Testing with: {}", $data);
+
+            let b64_data = $data.as_bytes().to_base64(
+                base64::Config {
+                    char_set: base64::CharacterSet::UrlSafe,
+                    newline: base64::Newline::LF,
+                    pad: true,
+                    line_length: None
+                });
+
+            trace!("Encoded as: {}", b64_data);
+
+            assert!($transfer.write_chunk(&b64_data, $index as u64, None).is_ok());
+            assert_eq!($data.into_bytes(),
+                       $storage.read_chunk(&$package, $index as u64).unwrap());
+        }}
+    }
+
+    #[test]
+    fn it_stores_decoded_data() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 1..20 {
+            let mut transfer = Transfer::new_test(storage.clone());
+            let package = transfer.randomize(i);
+            for i in 1..20 {
+                let data = rand::thread_rng()
+                    .gen_ascii_chars().take(i).collect::<String>();
+                assert_chunk_written!(transfer, storage, package, i, data);
+            }
+            assert_eq!(transfer.transferred_chunks, (1..20).collect::<Vec<u64>>());
+        }
+    }
+
+    #[test]
+    fn it_restores_persisted_transfers() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        transfer.checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
+        transfer.encoding = Encoding::Gzip;
+        transfer.chunkscount = 3;
+        let data = "test\n".as_bytes().to_base64(base64::STANDARD);
+        assert!(transfer.write_chunk(&data, 1, None).is_ok());
+        transfer.persist().unwrap();
+
+        let restored = Transfer::restore(storage.clone(), &package).unwrap();
+        assert_eq!(restored.checksum, transfer.checksum);
+        assert_eq!(restored.encoding, Encoding::Gzip);
+        assert_eq!(restored.chunkscount, 3);
+        assert_eq!(restored.transferred_chunks, vec!(1));
+        Transfer::restore(storage.clone(), &generate_random_package(11)).unwrap_err();
+    }
+
+    #[test]
+    fn it_rejects_invalid_base64() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+
+        match transfer.write_chunk("*invalid*", 0, None) {
+            Err(ChunkError::Corrupted(..)) => {},
+            _ => panic!("Accepted invalid base64!")
+        }
+        assert!(transfer.transferred_chunks.is_empty());
+        assert!(storage.chunks(&package).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_verifies_chunk_checksums() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        let data = "test\n".as_bytes().to_base64(base64::STANDARD);
+
+        let invalid = "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca";
+        match transfer.write_chunk(&data, 0, Some(invalid)) {
+            Err(ChunkError::Corrupted(..)) => {},
+            _ => panic!("Accepted a corrupted chunk!")
+        }
+        assert!(transfer.transferred_chunks.is_empty());
+        assert!(storage.chunks(&package).unwrap().is_empty());
+
+        let valid = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83";
+        assert!(transfer.write_chunk(&data, 0, Some(valid)).is_ok());
+        assert_eq!(transfer.transferred_chunks, vec!(0));
+    }
+
+    #[test]
+    fn it_tells_storage_failures_from_corrupted_chunks() {
+        test_init!();
+        let prefix = PathPrefix::new();
+        fs::create_dir_all(prefix.to_string()).unwrap();
+        File::create(format!("{}/downloads", prefix)).unwrap();
+        let mut transfer = Transfer::new_test(Arc::new(FileStorage::new(&prefix.to_string())));
+        transfer.randomize(10);
+
+        let data = "test\n".as_bytes().to_base64(base64::STANDARD);
+        match transfer.write_chunk(&data, 0, None) {
+            Err(ChunkError::Storage(..)) => {},
+            _ => panic!("Didn't report the storage failure!")
+        }
+        assert!(transfer.transferred_chunks.is_empty());
+    }
+
+    #[test]
+    fn it_removes_the_chunks_when_dropped() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 1..20 {
+            let mut transfer = Transfer::new_test(storage.clone());
+            let package = transfer.randomize(i);
+            let data = "test".to_string();
+            let index = 0;
+            assert_chunk_written!(transfer, storage, package, index, data);
+
+            drop(transfer);
+            assert!(storage.chunks(&package).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn it_correctly_assembles_stored_chunks() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        for i in 1..20 {
+            let mut transfer = Transfer::new_test(storage.clone());
+            let package = transfer.randomize(i);
+            let mut full_data = String::new();
+            for i in 1..20 {
+                let data = rand::thread_rng()
+                    .gen_ascii_chars().take(i).collect::<String>();
+                full_data.push_str(&data);
+
+                assert_chunk_written!(transfer, storage, package, i, data);
+            }
+
+            let writer = transfer.assemble_chunks().unwrap();
+            let mut hasher = Sha1::new();
+            hasher.input(full_data.as_bytes());
+            writer.commit(&hasher.result_str()).unwrap();
+
+            assert_eq!(full_data.into_bytes(), storage.read_package(&package).unwrap());
+        }
+    }
+
+    fn checksum_matching(data: String, checksum: String) -> bool {
+            let storage = Arc::new(MemoryStorage::new());
+            let mut transfer = Transfer::new_test(storage.clone());
+            let package = transfer.randomize(20);
+            let index = 0;
+            assert_chunk_written!(transfer, storage, package, index, data);
+
+            transfer.checksum = checksum;
+            let success = transfer.assemble_package();
+            assert_eq!(storage.read_package(&package).is_ok(), success);
+            success
+    }
+
+    #[test]
+    fn it_returns_true_for_correct_checksums() {
+        test_init!();
+        assert!(checksum_matching("test\n".to_string(),
+        "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string()));
+    }
+
+    #[test]
+    fn it_returns_false_for_incorrect_checksums() {
+        test_init!();
+        assert!(!checksum_matching("test\n".to_string(),
+        "fa7c4d75bae3a641d1f9ab5df028175bfb8a69ca".to_string()));
+    }
+
+    #[test]
+    fn it_applies_deltas_against_the_base_package() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let base = generate_random_package(10);
+        let mut staged = storage.stage_package(&base).unwrap();
+        staged.write_all(b"tesd\n").unwrap();
+        staged.commit().unwrap();
+
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        transfer.base = Some(base);
+        transfer.checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
+
+        // Adds 1 to the 'd' of the base
+        let mut patch = b"SOTADIFF".to_vec();
+        for n in [5u64, 5, 0, 0].iter() {
+            for i in 0..8 {
+                patch.push((n >> (i * 8)) as u8);
+            }
+        }
+        patch.extend_from_slice(&[0, 0, 0, 1, 0]);
+        assert!(transfer.write_chunk(&patch.to_base64(base64::STANDARD), 0, None).is_ok());
+
+        assert!(transfer.assemble_package());
+        assert_eq!(storage.read_package(&package).unwrap(), b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_fails_deltas_without_a_base_package() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        transfer.base = Some(generate_random_package(10));
+        transfer.checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
+        assert!(transfer.write_chunk(&b"SOTADIFF".to_base64(base64::STANDARD), 0, None).is_ok());
+
+        assert!(!transfer.assemble_package());
+        assert!(!storage.has_package(&package));
+    }
+
+    #[test]
+    fn it_decompresses_gzip_encoded_chunks() {
+        test_init!();
+        let storage = Arc::new(MemoryStorage::new());
+        let mut transfer = Transfer::new_test(storage.clone());
+        let package = transfer.randomize(10);
+        transfer.encoding = Encoding::Gzip;
+        transfer.checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(b"test\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        assert!(transfer.write_chunk(&first.to_base64(base64::STANDARD), 0, None).is_ok());
+        assert!(transfer.write_chunk(&second.to_base64(base64::STANDARD), 1, None).is_ok());
+
+        assert!(transfer.assemble_package());
+        assert_eq!(storage.read_package(&package).unwrap(), b"test\n".to_vec());
+    }
+
+    #[test]
+    fn it_returns_false_for_invalid_checksums() {
+        test_init!();
+        assert!(!checksum_matching("test\n".to_string(),
+        "invalid".to_string()));
+    }
+}
@@ -0,0 +1,142 @@
+//! Policies for installing updates without confirmation by the user, configured in the `policy`
+//! section of the configuration.
+
+use time::Tm;
+
+use configuration::{PolicyConfiguration, any_open};
+use message::UserPackage;
+
+/// Check whether `package` should be downloaded and installed without waiting for the user. That
+/// is the case, if automatic installation is enabled, the update is mandatory or its name matches
+/// one of the configured patterns, and it satisfies the size limit and one of the time windows.
+///
+/// # Arguments
+/// * `policy`: The `policy` section of the configuration.
+/// * `package`: The announced update.
+/// * `now`: The current local time.
+pub fn auto_install(policy: &PolicyConfiguration, package: &UserPackage, now: &Tm) -> bool {
+    if !policy.auto_install {
+        return false;
+    }
+
+    let mandatory = policy.mandatory && package.mandatory.unwrap_or(false);
+    let named = policy.packages.iter().any(|p| matches_pattern(p, &package.package.name));
+    let fits = policy.max_size.map(|max| package.size as i64 <= max).unwrap_or(true);
+    let in_window = any_open(&policy.windows, now);
+
+    (mandatory || named) && fits && in_window
+}
+
+/// Match `name` against `pattern`, where `*` matches any sequence of characters.
+///
+/// # Arguments
+/// * `pattern`: The pattern to match.
+/// * `name`: The package name to check.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if name.len() < first.len() + last.len() || !name.starts_with(first) ||
+        !name.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::matches_pattern;
+    use test_library::generate_random_package;
+
+    use time;
+
+    use configuration::PolicyConfiguration;
+    use message::{PackageId, UserPackage};
+
+    fn gen_update(name: &str, size: u64, mandatory: bool) -> UserPackage {
+        UserPackage {
+            package: PackageId {
+                name: name.to_string(),
+                version: generate_random_package(5).version
+            },
+            size: size,
+            priority: None,
+            mandatory: Some(mandatory),
+            deadline: None,
+            description: None,
+            requires: None
+        }
+    }
+
+    fn gen_policy() -> PolicyConfiguration {
+        let mut policy = PolicyConfiguration::gen_test();
+        policy.auto_install = true;
+        policy.mandatory = true;
+        policy.packages = vec!("telematics-*".to_string());
+        policy
+    }
+
+    #[test]
+    fn it_matches_name_patterns() {
+        test_init!();
+        assert!(matches_pattern("telematics", "telematics"));
+        assert!(matches_pattern("telematics-*", "telematics-unit"));
+        assert!(matches_pattern("*-maps-*", "eu-maps-2016"));
+        assert!(matches_pattern("a*b*a", "aba"));
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(!matches_pattern("telematics-*", "infotainment"));
+    }
+
+    #[test]
+    fn it_installs_matching_updates() {
+        test_init!();
+        let policy = gen_policy();
+        let now = time::now();
+        assert!(auto_install(&policy, &gen_update("telematics-unit", 512, false), &now));
+        assert!(auto_install(&policy, &gen_update("infotainment", 512, true), &now));
+        assert!(!auto_install(&policy, &gen_update("infotainment", 512, false), &now));
+    }
+
+    #[test]
+    fn it_respects_the_size_limit_and_window() {
+        test_init!();
+        let mut policy = gen_policy();
+        let update = gen_update("telematics-unit", 512, false);
+        let mut now = time::empty_tm();
+        now.tm_hour = 12;
+
+        policy.max_size = Some(511);
+        assert!(!auto_install(&policy, &update, &now));
+
+        policy.max_size = None;
+        policy.windows = vec!("22:00-05:00".parse().unwrap(), "Sun 11:00-13:00".parse().unwrap());
+        assert!(!auto_install(&policy, &update, &now));
+        now.tm_hour = 23;
+        assert!(auto_install(&policy, &update, &now));
+        now.tm_hour = 12;
+        now.tm_wday = 0;
+        assert!(auto_install(&policy, &update, &now));
+    }
+
+    #[test]
+    fn it_installs_nothing_unless_enabled() {
+        test_init!();
+        let mut policy = gen_policy();
+        policy.auto_install = false;
+        let update = gen_update("telematics-unit", 512, true);
+        assert!(!auto_install(&policy, &update, &time::now()));
+    }
+}
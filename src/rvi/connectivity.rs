@@ -0,0 +1,52 @@
+//! Tracking of the services on other RVI nodes, that RVI announced as available.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Holds the remote services, that RVI announced through the `services_available` callback and
+/// didn't withdraw through `services_unavailable` since.
+///
+/// RVI accepts messages for services, that currently can't be reached, and delivers them once
+/// their node connects. A message, that was sent successfully, therefore doesn't tell whether the
+/// server is connected, the announced services do.
+#[derive(Clone)]
+pub struct Connectivity {
+    services: Arc<Mutex<HashSet<String>>>
+}
+
+impl Connectivity {
+    /// Create a new `Connectivity`, without any available services.
+    pub fn new() -> Connectivity {
+        Connectivity {
+            services: Arc::new(Mutex::new(HashSet::new()))
+        }
+    }
+
+    /// Record `services` as available.
+    ///
+    /// # Arguments
+    /// * `services`: The full names of the services, that became available.
+    pub fn available(&self, services: &[String]) {
+        let mut available = self.services.lock().unwrap();
+        for service in services {
+            let _ = available.insert(service.clone());
+        }
+    }
+
+    /// Record `services` as unavailable.
+    ///
+    /// # Arguments
+    /// * `services`: The full names of the services, that became unavailable.
+    pub fn unavailable(&self, services: &[String]) {
+        let mut available = self.services.lock().unwrap();
+        for service in services {
+            let _ = available.remove(service);
+        }
+    }
+
+    /// Returns whether any remote service is currently available, i.e. whether RVI is connected
+    /// to the server.
+    pub fn is_connected(&self) -> bool {
+        !self.services.lock().unwrap().is_empty()
+    }
+}
@@ -7,12 +7,14 @@
 //!
 //! It is intended to be split out into a separate crate at some point in the future.
 
+mod connectivity;
 mod edge;
 mod send;
 mod message;
 mod handler;
 
 // Export public interface
+pub use rvi::connectivity::Connectivity;
 pub use rvi::edge::ServiceEdge;
 pub use rvi::edge::Service;
 pub use rvi::handler::RVIHandler;
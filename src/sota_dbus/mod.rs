@@ -5,5 +5,6 @@
 mod sender;
 mod receiver;
 
-pub use self::sender::{send_notify, request_install, request_report};
+pub use self::sender::{send_notify, send_download_failed, request_install, request_uninstall,
+                       request_report};
 pub use self::receiver::Receiver;
@@ -2,6 +2,7 @@
 
 use std::sync::mpsc::Sender;
 
+use rvi::Connectivity;
 use configuration::DBusConfiguration;
 use message::{PackageId, Notification};
 
@@ -29,7 +30,9 @@ pub struct Receiver {
     /// The configuration for the DBus interface.
     config: DBusConfiguration,
     /// A sender to forward incoming messages.
-    sender: Sender<Notification>
+    sender: Sender<Notification>,
+    /// The remote services, RVI announced as available.
+    connectivity: Connectivity
 }
 
 impl Receiver {
@@ -38,10 +41,13 @@ impl Receiver {
     /// # Arguments
     /// * `c`: The configuration for the DBus interface.
     /// * `s`: A sender to forward incoming messages.
-    pub fn new(c: DBusConfiguration, s: Sender<Notification>) -> Receiver {
+    /// * `connectivity`: The remote services, RVI announced as available.
+    pub fn new(c: DBusConfiguration, s: Sender<Notification>,
+               connectivity: Connectivity) -> Receiver {
         Receiver {
             config: c,
-            sender: s
+            sender: s,
+            connectivity: connectivity
         }
     }
 
@@ -59,7 +65,13 @@ impl Receiver {
                         vec!(Argument::new("Status", "b")),
                         Box::new(|msg| self.handle_initiate(msg)));
 
-        let interface = Interface::new(vec!(initiate_method), vec!(), vec!());
+        let status_method =
+            Method::new("GetConnectionStatus",
+                        vec!(),
+                        vec!(Argument::new("Connected", "b")),
+                        Box::new(|msg| self.handle_status(msg)));
+
+        let interface = Interface::new(vec!(initiate_method, status_method), vec!(), vec!());
 
         object_path.insert_interface(&self.config.interface, interface);
         object_path.set_registered(true).unwrap();
@@ -93,6 +105,19 @@ impl Receiver {
 
         Ok(vec!(MessageItem::Bool(true)))
     }
+
+    /// Handles incoming "Get Connection Status" messages.
+    ///
+    /// Replies whether RVI is connected to the server, i.e. whether RVI announced any of the
+    /// server's services as available. This says nothing about the local RVI node, which accepts
+    /// messages for the server even while it is disconnected.
+    ///
+    /// # Arguments
+    /// * `msg`: The message to handle.
+    fn handle_status(&self, msg: &mut Message) -> MethodResult {
+        trace!("msg: {:?}", msg);
+        Ok(vec!(MessageItem::Bool(self.connectivity.is_connected())))
+    }
 }
 
 #[cfg(not(test))]
@@ -116,13 +141,14 @@ mod test {
     use super::*;
     use message::Notification;
     use configuration::DBusConfiguration;
+    use rvi::Connectivity;
     use test_library::generate_random_package;
 
     macro_rules! setup_receiver {
         () => {{
             let (tx, rx) = channel();
             let config = DBusConfiguration::gen_test();
-            let receiver = Receiver::new(config.clone(), tx);
+            let receiver = Receiver::new(config.clone(), tx, Connectivity::new());
             let message =
                 Message::new_method_call(&config.name, "/", &config.interface,
                                         "InitiateDownload").unwrap();
@@ -147,6 +173,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_replies_with_the_connection_status() {
+        test_init!();
+        let (_, receiver, mut message) = setup_receiver!();
+        let service = vec!("genivi.org/backend/sota/start".to_string());
+        for &connected in [false, true, false].iter() {
+            if connected {
+                receiver.connectivity.available(&service);
+            } else {
+                receiver.connectivity.unavailable(&service);
+            }
+            match receiver.handle_status(&mut message).unwrap().pop() {
+                Some(MessageItem::Bool(status)) => assert_eq!(status, connected),
+                _ => panic!("Didn't reply with a boolean!")
+            }
+        }
+    }
+
     #[test]
     fn it_returns_an_error_on_incorrect_messages() {
         test_init!();
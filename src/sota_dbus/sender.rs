@@ -9,28 +9,54 @@ use configuration::DBusConfiguration;
 use message::{UserPackage, PackageId, PackageReport};
 use message::ParsePackageReport;
 
-/// Foward a "Notify" message to DBus.
+/// Foward a "Notify" message to DBus. Software Loading Managers, that enabled `notify_updates`,
+/// receive a "NotifyUpdates" message with the scheduling metadata of the updates instead.
 ///
 /// # Arguments
 /// * `config`: The configuration of the DBus interface.
 /// * `packages`: `Vector` of the packages that need updating.
 pub fn send_notify(config: &DBusConfiguration, packages: Vec<UserPackage>) {
     let connection = Connection::get_private(BusType::Session).unwrap();
+    let method = if config.notify_updates { "NotifyUpdates" } else { "Notify" };
     let mut message =
         Message::new_method_call(&config.software_manager, "/",
-                                 &config.software_manager, "Notify")
+                                 &config.software_manager, method)
         .unwrap();
 
     let mut message_items = Vec::new();
     for package in packages {
-        message_items.push(MessageItem::from(package));
+        if config.notify_updates {
+            message_items.push(package.to_update_item());
+        } else {
+            message_items.push(MessageItem::from(package));
+        }
     }
 
     // hardcoded signature as a workaround for diwic/dbus-rs#24
     // needs to stay in until the fix is released and works on stable
-    let args = [MessageItem::Array(message_items,
-                                   Cow::Owned("(a{ss}t)".to_string()))];
+    let signature = if config.notify_updates { "(a{ss}tubxs)" } else { "(a{ss}t)" };
+    let args = [MessageItem::Array(message_items, Cow::Owned(signature.to_string()))];
+
+    message.append_items(&args);
+    if connection.send(message).is_err() {
+        error!("Couldn't forward message to D-Bus");
+    }
+}
+
+/// Tell the Software Loading Manager, that the download of a package failed and was dropped.
+///
+/// # Arguments
+/// * `config`: The configuration of the DBus interface.
+/// * `package`: The package, whose download failed.
+/// * `reason`: A short description of the failure.
+pub fn send_download_failed(config: &DBusConfiguration, package: PackageId, reason: &str) {
+    let connection = Connection::get_private(BusType::Session).unwrap();
+    let mut message =
+        Message::new_method_call(&config.software_manager, "/",
+                                 &config.software_manager, "DownloadFailed")
+        .unwrap();
 
+    let args = [MessageItem::from(&package), MessageItem::Str(reason.to_string())];
     message.append_items(&args);
     if connection.send(message).is_err() {
         error!("Couldn't forward message to D-Bus");
@@ -59,6 +85,27 @@ pub fn request_install(config: &DBusConfiguration, package: PackageId)
             .parse(package)
     }
 
+/// Ask the Software Loading Manager to remove a package. Will block until the package was removed
+/// or the timeout is reached.
+///
+/// # Arguments
+/// * `config`: The configuration of the DBus interface.
+/// * `package`: The package to remove.
+pub fn request_uninstall(config: &DBusConfiguration, package: PackageId) -> PackageReport {
+    let connection = Connection::get_private(BusType::Session).unwrap();
+    let mut message =
+        Message::new_method_call(&config.software_manager, "/",
+                                 &config.software_manager,
+                                 "UninstallPackage").unwrap();
+
+    let args = [MessageItem::from(&package)];
+    message.append_items(&args);
+
+    connection
+        .send_with_reply_and_block(message, config.timeout)
+        .parse(package)
+}
+
 /// Request a full report from the Software Loading Manager. Will block until the list of all
 /// installed packages is received or the timeout is reached.
 ///
@@ -117,16 +164,38 @@ mod test {
     use message::UserPackage;
     use test_library::generate_random_package;
 
+    fn gen_packages() -> Vec<UserPackage> {
+        vec!(UserPackage {
+            package: generate_random_package(15),
+            size: 500,
+            priority: Some(5),
+            mandatory: None,
+            deadline: None,
+            description: None,
+            requires: None
+        })
+    }
+
     #[test]
     fn it_sets_a_valid_notify_signature() {
         test_init!();
         let conf = DBusConfiguration::gen_test();
-        let packages = vec!(UserPackage {
-            package: generate_random_package(15),
-            size: 500
-        });
+        send_notify(&conf, gen_packages());
+    }
+
+    #[test]
+    fn it_sets_a_valid_notify_updates_signature() {
+        test_init!();
+        let mut conf = DBusConfiguration::gen_test();
+        conf.notify_updates = true;
+        send_notify(&conf, gen_packages());
+    }
 
-        send_notify(&conf, packages);
+    #[test]
+    fn it_sets_a_valid_download_failed_signature() {
+        test_init!();
+        let conf = DBusConfiguration::gen_test();
+        send_download_failed(&conf, generate_random_package(15), "Transfer timed out");
     }
 
     #[test]
@@ -136,6 +205,13 @@ mod test {
         request_install(&conf, generate_random_package(15));
     }
 
+    #[test]
+    fn it_sets_a_valid_uninstall_signature() {
+        test_init!();
+        let conf = DBusConfiguration::gen_test();
+        request_uninstall(&conf, generate_random_package(15));
+    }
+
     fn gen_test_message() -> Message {
         let config = DBusConfiguration::gen_test();
         Message::new_method_call(&config.name, "/", &config.interface,
@@ -93,6 +93,9 @@ pub fn get_empty_backend() -> BackendServices {
         start: "".to_string(),
         ack: "".to_string(),
         report: "".to_string(),
-        packages: "".to_string()
+        packages: "".to_string(),
+        full_package: None,
+        deferred: None,
+        install_deferred: None
     }
 }
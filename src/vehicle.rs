@@ -0,0 +1,171 @@
+//! Checks of the vehicle state, that has to be met before packages are installed, configured in the
+//! `vehicle` section of the configuration.
+
+use std::process::Command;
+
+use dbus::{Connection, BusType, MessageItem, Props};
+
+use configuration::VehicleConfiguration;
+
+/// Check all configured preconditions. Returns a `String` with the reason, why packages can't be
+/// installed right now, if a precondition isn't met or can't be checked.
+///
+/// # Arguments
+/// * `conf`: The `vehicle` section of the configuration.
+/// * `timeout`: Timeout for D-Bus calls in milliseconds.
+pub fn check_preconditions(conf: &VehicleConfiguration, timeout: i32) -> Result<(), String> {
+    if let Some(ref command) = conf.command {
+        try!(run_check(command));
+    }
+
+    if let Some(ref service) = conf.state_service {
+        let ignition = if conf.ignition_off {
+            Some(try!(read_property(service, "Ignition", timeout).and_then(as_bool)))
+        } else {
+            None
+        };
+        let battery = match conf.min_battery {
+            Some(_) => Some(try!(read_property(service, "BatteryLevel", timeout)
+                                 .and_then(as_integer))),
+            None => None
+        };
+        let parking_brake = if conf.parking_brake {
+            Some(try!(read_property(service, "ParkingBrake", timeout).and_then(as_bool)))
+        } else {
+            None
+        };
+        try!(evaluate(conf, ignition, battery, parking_brake));
+    }
+
+    Ok(())
+}
+
+/// Check the read vehicle state against the configured preconditions.
+///
+/// # Arguments
+/// * `conf`: The `vehicle` section of the configuration.
+/// * `ignition`: Whether the ignition is on, if checked.
+/// * `battery`: The battery level in percent, if checked.
+/// * `parking_brake`: Whether the parking brake is engaged, if checked.
+fn evaluate(conf: &VehicleConfiguration, ignition: Option<bool>, battery: Option<i64>,
+            parking_brake: Option<bool>) -> Result<(), String> {
+    if conf.ignition_off && ignition.unwrap_or(true) {
+        return Err("Ignition is on".to_string());
+    }
+    if let (Some(min), Some(level)) = (conf.min_battery, battery) {
+        if level < min {
+            return Err(format!("Battery level {}% is below {}%", level, min));
+        }
+    }
+    if conf.parking_brake && !parking_brake.unwrap_or(false) {
+        return Err("Parking brake isn't engaged".to_string());
+    }
+    Ok(())
+}
+
+/// Run the local check `command` with `sh`. Returns the first line of its output as error, if it
+/// exits with a non-zero status.
+///
+/// # Arguments
+/// * `command`: The command to run.
+fn run_check(command: &str) -> Result<(), String> {
+    let output = try!(Command::new("sh").arg("-c").arg(command).output()
+                      .map_err(|e| format!("Couldn't run \"{}\": {}", command, e)));
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next() {
+        Some(line) if !line.trim().is_empty() => Err(line.trim().to_string()),
+        _ => Err(format!("Vehicle check failed with {}", output.status))
+    }
+}
+
+/// Read the property `name` of the vehicle state `service` over D-Bus.
+///
+/// # Arguments
+/// * `service`: D-Bus name and interface of the service.
+/// * `name`: The property to read.
+/// * `timeout`: Timeout for the D-Bus call in milliseconds.
+fn read_property(service: &str, name: &str, timeout: i32) -> Result<MessageItem, String> {
+    let connection = try!(Connection::get_private(BusType::Session)
+                          .map_err(|e| format!("Couldn't connect to D-Bus: {:?}", e)));
+    let props = Props::new(&connection, service, "/", service, timeout);
+    match props.get(name) {
+        Ok(MessageItem::Variant(item)) => Ok(*item),
+        Ok(item) => Ok(item),
+        Err(e) => Err(format!("Couldn't read vehicle state {}: {:?}", name, e))
+    }
+}
+
+/// Convert a D-Bus `item` to a `bool`.
+///
+/// # Arguments
+/// * `item`: The item to convert.
+fn as_bool(item: MessageItem) -> Result<bool, String> {
+    match item {
+        MessageItem::Bool(b) => Ok(b),
+        _ => Err(format!("Expected a boolean vehicle state, got {:?}", item))
+    }
+}
+
+/// Convert a numeric D-Bus `item` to a `i64`.
+///
+/// # Arguments
+/// * `item`: The item to convert.
+fn as_integer(item: MessageItem) -> Result<i64, String> {
+    match item {
+        MessageItem::Byte(n) => Ok(n as i64),
+        MessageItem::Int32(n) => Ok(n as i64),
+        MessageItem::UInt32(n) => Ok(n as i64),
+        MessageItem::Int64(n) => Ok(n),
+        MessageItem::UInt64(n) => Ok(n as i64),
+        _ => Err(format!("Expected a numeric vehicle state, got {:?}", item))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::evaluate;
+
+    use configuration::VehicleConfiguration;
+
+    fn gen_conf() -> VehicleConfiguration {
+        let mut conf = VehicleConfiguration::gen_test();
+        conf.ignition_off = true;
+        conf.min_battery = Some(30);
+        conf.parking_brake = true;
+        conf
+    }
+
+    #[test]
+    fn it_accepts_a_parked_vehicle() {
+        test_init!();
+        evaluate(&gen_conf(), Some(false), Some(80), Some(true)).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_unmet_preconditions() {
+        test_init!();
+        let conf = gen_conf();
+        assert_eq!(evaluate(&conf, Some(true), Some(80), Some(true)),
+                   Err("Ignition is on".to_string()));
+        assert_eq!(evaluate(&conf, Some(false), Some(20), Some(true)),
+                   Err("Battery level 20% is below 30%".to_string()));
+        assert_eq!(evaluate(&conf, Some(false), Some(80), Some(false)),
+                   Err("Parking brake isn't engaged".to_string()));
+    }
+
+    #[test]
+    fn it_runs_the_check_command() {
+        test_init!();
+        let mut conf = VehicleConfiguration::gen_test();
+        conf.command = Some("true".to_string());
+        check_preconditions(&conf, 1000).unwrap();
+
+        conf.command = Some("echo 'Vehicle is moving'; exit 1".to_string());
+        assert_eq!(check_preconditions(&conf, 1000), Err("Vehicle is moving".to_string()));
+    }
+}